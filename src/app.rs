@@ -1,4 +1,11 @@
-use std::fs;
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
 use embedded_graphics::{
     mono_font::{
@@ -8,353 +15,1691 @@ use embedded_graphics::{
     pixelcolor::BinaryColor,
     prelude::*,
     primitives::*,
-    text::{Alignment, Text},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
 };
-use walkdir::WalkDir;
+#[cfg(feature = "fake")]
+use std::{cell::RefCell, rc::Rc};
+use tracing::{debug, error, info, warn};
 
 use crate::{
-    config::Config,
-    device::{get_devices, Device},
+    automount,
+    config::{AutomountMode, Config},
+    device::{self, BlockDeviceBackend, Device, DeviceError, MountOptions},
+    hotplug::HotplugEvent,
+    input::{Button, Event},
+    logging::LogBuffer,
+    naming,
+    smb::{self, Share, SmbBackend},
+    state::{OwnedMount, State},
+    wifi::{Network, WifiBackend, WifiState},
 };
+#[cfg(feature = "fake")]
+use crate::{device::FakeBlockDevices, smb::FakeSmb, wifi::FakeWifi};
+use crate::{device::Lsblk, smb::Smbd, wifi::Nmcli};
+
+/// How long transient notifications stay on top of the current screen
+const NOTIFICATION_TIME: Duration = Duration::from_secs(3);
+
+struct Notification {
+    msg: String,
+    expires: Instant,
+}
 
-#[derive(Default)]
 pub struct App {
     screen: Screen,
+    notification: Option<Notification>,
     should_quit: bool,
     config: Config,
+    /// Devices to mount read only, by name
+    read_only: HashSet<String>,
+    block: Box<dyn BlockDeviceBackend>,
+    wifi: Box<dyn WifiBackend>,
+    smb: Box<dyn SmbBackend>,
+    /// What has been changed on disk, saved to `config.state_path`
+    state: State,
+    /// Recent log entries for the log screen
+    logs: LogBuffer,
+    /// Display area from the last draw, the log screen wraps text to fit it
+    bounds: Cell<Rectangle>,
 }
 
 impl App {
+    /// An app on the real tools, logging into `logs` for the log screen
+    pub fn new(config: Config, logs: LogBuffer) -> Self {
+        let mut app = Self::with_backends(Box::new(Lsblk), Box::new(Nmcli), Box::new(Smbd));
+        app.config = config;
+        app.logs = logs;
+
+        app
+    }
+
+    /// An app on the in memory backends with a demo drive and a couple of
+    /// networks, the returned handle plugs in more
+    ///
+    /// The keys and layout come from `config`, but nothing that could touch
+    /// real mounts does.
+    #[cfg(feature = "fake")]
+    pub fn demo(mut config: Config, logs: LogBuffer) -> (Self, Rc<RefCell<FakeBlockDevices>>) {
+        let block = Rc::new(RefCell::new(FakeBlockDevices::default()));
+        block.borrow_mut().plug("sda1", "64G");
+
+        let wifi = FakeWifi {
+            networks: vec![
+                Network {
+                    ssid: "Home".to_string(),
+                    signal: 82,
+                    secured: true,
+                },
+                Network {
+                    ssid: "Cafe".to_string(),
+                    signal: 40,
+                    secured: false,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let mut app = Self::with_backends(
+            Box::new(block.clone()),
+            Box::new(wifi),
+            Box::new(FakeSmb::default()),
+        );
+
+        config.mount_path = std::env::temp_dir().join("drive-pi-demo");
+        config.state_path = config.mount_path.join("state.json");
+        app.config = config;
+        app.logs = logs;
+
+        (app, block)
+    }
+
+    pub fn with_backends(
+        block: Box<dyn BlockDeviceBackend>,
+        wifi: Box<dyn WifiBackend>,
+        smb: Box<dyn SmbBackend>,
+    ) -> Self {
+        Self {
+            screen: Screen::default(),
+            notification: None,
+            should_quit: false,
+            config: Config::default(),
+            read_only: HashSet::new(),
+            block,
+            wifi,
+            smb,
+            state: State::default(),
+            logs: LogBuffer::default(),
+            bounds: Cell::new(Rectangle::new(Point::zero(), Size::new(128, 64))),
+        }
+    }
+
     pub fn should_exit(&self) -> bool {
         self.should_quit
     }
 
-    pub fn handle_input(&mut self, input: &str) {
+    /// Feeds one event into the app, returns true if the display needs
+    /// redrawing
+    pub fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Press(button) => {
+                self.handle_input(button);
+                true
+            }
+            // Hold BACK to go Home from anywhere
+            Event::LongPress(Button::BottomRight) => {
+                self.screen = Screen::Home;
+                true
+            }
+            // A held button has not sent a press yet, so the long press stands
+            // in for it
+            Event::LongPress(button) => {
+                self.handle_input(button);
+                true
+            }
+            Event::Repeat(button) => {
+                if !self.screen.scrolls(button) {
+                    return false;
+                }
+
+                self.handle_input(button);
+                true
+            }
+            // Hold both top corners for the exit menu
+            Event::Chord(Button::TopLeft, Button::TopRight)
+            | Event::Chord(Button::TopRight, Button::TopLeft) => {
+                self.screen = Screen::ConfirmExit;
+                true
+            }
+            // Hold both bottom corners on Home for the logs
+            Event::Chord(Button::BottomLeft, Button::BottomRight)
+            | Event::Chord(Button::BottomRight, Button::BottomLeft)
+                if matches!(self.screen, Screen::Home) =>
+            {
+                self.logs(None);
+                true
+            }
+            Event::Chord(..) => false,
+            Event::Hotplug(event) => {
+                self.handle_hotplug(event);
+                true
+            }
+            Event::Tick(now) => self.tick(now),
+            Event::Shutdown => {
+                self.should_quit = true;
+                true
+            }
+        }
+    }
+
+    pub fn handle_input(&mut self, button: Button) {
         match &self.screen {
-            Screen::Home => match input {
+            Screen::Home => match button {
                 // WiFi
-                "a" | "7" => {
-                    self.screen = Screen::Error("WiFi not implemented".to_string());
+                Button::TopLeft => {
+                    self.wifi(None);
                 }
                 // Mnt
-                "c" | "9" => {
+                Button::TopRight => {
                     self.devices(None);
                 }
                 // SMB
-                "b" | "1" => {
-                    self.screen = Screen::Error("Samba password\nnot implemented".to_string());
+                Button::BottomLeft => {
+                    self.smb(None);
                 }
                 // Reboot
-                "d" | "3" => {
+                Button::BottomRight => {
                     self.screen = Screen::ConfirmExit;
                 }
-                _ => {}
             },
-            Screen::Devices(devices, idex) => match input {
-                "a" | "7" => {
-                    if *idex == 0 {
-                        self.screen = Screen::Devices(devices.clone(), devices.len() - 1)
+            Screen::Devices(devices, idex) => match button {
+                Button::TopLeft if !devices.is_empty() => {
+                    let read_only = self.read_only(&devices[*idex]);
+                    self.screen = Screen::DeviceDetail(devices.clone(), *idex, read_only)
+                }
+                Button::TopRight if !devices.is_empty() => {
+                    let idex = *idex;
+                    let dev = devices[idex].clone();
+                    if dev.mounted {
+                        self.eject(&dev);
+                    } else if self.mount_selected(&dev) {
+                        self.devices(Some(idex));
+                    }
+                }
+                Button::BottomLeft if !devices.is_empty() => {
+                    if *idex == devices.len() - 1 {
+                        self.screen = Screen::Devices(devices.clone(), 0)
                     } else {
-                        self.screen = Screen::Devices(devices.clone(), idex - 1)
+                        self.screen = Screen::Devices(devices.clone(), idex + 1)
+                    }
+                }
+                Button::BottomRight => self.screen = Screen::Home,
+                // Nothing to select
+                Button::TopLeft | Button::TopRight | Button::BottomLeft => {}
+            },
+            Screen::DeviceDetail(devices, idex, read_only) => match button {
+                Button::TopLeft => {
+                    let idex = *idex;
+                    let dev = devices[idex].clone();
+                    if dev.mounted {
+                        self.eject(&dev);
+                    } else if self.mount_selected(&dev) {
+                        self.device_detail(&dev.name, idex);
                     }
                 }
-                "c" | "9" => {
-                    let dev = &devices[*idex];
+                Button::TopRight => {
+                    let (idex, read_only) = (*idex, !*read_only);
+                    let dev = devices[idex].clone();
+
                     if dev.mounted {
-                        match dev.unmount() {
-                            Ok(_) => {
-                                match fs::remove_dir(&dev.path) {
-                                    _ => {}
-                                };
-                                self.devices(Some(*idex));
-                            }
-                            Err(ex) => {
-                                eprintln!("Could not unmount {ex:?}");
-                                self.screen =
-                                    Screen::Error(format!("Could not unmount {}", dev.name));
-                            }
+                        if let Err(ex) = self.block.remount(&dev, read_only) {
+                            error!(device = %dev.name, read_only, error = %ex, "Could not remount");
+                            self.show_error(device_error(
+                                &ex,
+                                Some(&dev),
+                                format!("Could not remount {}", dev.name),
+                            ));
+                            return;
                         }
+                        info!(device = %dev.name, read_only, "Remounted");
+
+                        self.state.set_read_only(&dev.path, read_only);
+                        self.save_state();
+                        self.update_shares();
+                    } else if read_only {
+                        self.read_only.insert(dev.name.clone());
+                    } else {
+                        self.read_only.remove(&dev.name);
+                    }
 
-                        self.devices(None);
+                    self.device_detail(&dev.name, idex);
+                }
+                Button::BottomLeft => {}
+                Button::BottomRight => self.screen = Screen::Devices(devices.clone(), *idex),
+            },
+            Screen::Wifi(state, networks, idex) => match button {
+                Button::TopLeft if !networks.is_empty() => {
+                    if *idex == 0 {
+                        self.screen =
+                            Screen::Wifi(state.clone(), networks.clone(), networks.len() - 1)
                     } else {
-                        match dev.mount() {
-                            Ok(_) => self.devices(Some(*idex)),
-                            Err(ex) => {
-                                eprintln!("{ex:?}");
-                                self.screen =
-                                    Screen::Error(format!("Could not mount {}", dev.name));
-                            }
+                        self.screen = Screen::Wifi(state.clone(), networks.clone(), idex - 1)
+                    }
+                }
+                Button::TopRight => {
+                    let idex = *idex;
+                    let result = match state {
+                        WifiState::Hotspot(_) => self.wifi.hotspot_down(&self.config.wifi),
+                        _ => self.wifi.hotspot_up(&self.config.wifi),
+                    };
+
+                    match result {
+                        Ok(_) => self.wifi(Some(idex)),
+                        Err(ex) => {
+                            error!(error = %ex, "Could not toggle hotspot");
+                            self.show_error("Could not toggle\nhotspot".to_string());
                         }
                     }
                 }
-                "b" | "1" => {
-                    if *idex == devices.len() - 1 {
-                        self.screen = Screen::Devices(devices.clone(), 0)
+                Button::BottomLeft if !networks.is_empty() => {
+                    if *idex == networks.len() - 1 {
+                        self.screen = Screen::Wifi(state.clone(), networks.clone(), 0)
                     } else {
-                        self.screen = Screen::Devices(devices.clone(), idex + 1)
+                        self.screen = Screen::Wifi(state.clone(), networks.clone(), idex + 1)
+                    }
+                }
+                Button::BottomRight => self.screen = Screen::Home,
+                // Nothing to scroll
+                Button::TopLeft | Button::BottomLeft => {}
+            },
+            Screen::Smb(shares, idex) => match button {
+                Button::TopLeft if !shares.is_empty() => {
+                    if *idex == 0 {
+                        self.screen = Screen::Smb(shares.clone(), shares.len() - 1)
+                    } else {
+                        self.screen = Screen::Smb(shares.clone(), idex - 1)
+                    }
+                }
+                Button::TopRight => {
+                    let idex = *idex;
+                    match self
+                        .smb
+                        .set_password(&self.config.smb.username, &self.config.smb.password)
+                    {
+                        Ok(_) => self.smb(Some(idex)),
+                        Err(ex) => {
+                            error!(user = %self.config.smb.username, error = %ex, "Could not set SMB password");
+                            self.show_error(format!(
+                                "Could not set password\nfor {}",
+                                self.config.smb.username
+                            ));
+                        }
+                    }
+                }
+                Button::BottomLeft if !shares.is_empty() => {
+                    if *idex == shares.len() - 1 {
+                        self.screen = Screen::Smb(shares.clone(), 0)
+                    } else {
+                        self.screen = Screen::Smb(shares.clone(), idex + 1)
                     }
                 }
-                "d" | "3" => self.screen = Screen::Home,
-                _ => {}
+                Button::BottomRight => self.screen = Screen::Home,
+                // Nothing to scroll
+                Button::TopLeft | Button::BottomLeft => {}
+            },
+            Screen::Ejected(_) => match button {
+                Button::TopLeft => self.devices(None),
+                Button::TopRight | Button::BottomLeft | Button::BottomRight => {}
             },
-            Screen::Error(_) => match input {
-                "a" | "7" => self.screen = Screen::Home,
-                _ => {}
+            Screen::Error(_, related) => match button {
+                Button::TopLeft => self.screen = Screen::Home,
+                Button::TopRight => self.logs(*related),
+                Button::BottomLeft | Button::BottomRight => {}
             },
-            Screen::ConfirmExit => match input {
-                "a" | "7" => self.should_quit = true,
-                "c" | "9" => self.screen = Screen::Home,
-                _ => {}
+            Screen::Logs(lines, top) => {
+                let page = Layout::new(self.bounds.get()).lines();
+                let last_page = lines.len().saturating_sub(page);
+
+                match button {
+                    Button::TopLeft => {
+                        self.screen = Screen::Logs(lines.clone(), top.saturating_sub(page))
+                    }
+                    Button::BottomLeft => {
+                        self.screen = Screen::Logs(lines.clone(), (top + page).min(last_page))
+                    }
+                    Button::BottomRight => self.screen = Screen::Home,
+                    Button::TopRight => {}
+                }
+            }
+            Screen::ConfirmExit => match button {
+                Button::TopLeft => self.should_quit = true,
+                Button::TopRight => self.screen = Screen::Home,
+                Button::BottomLeft => self.logs(None),
+                Button::BottomRight => {}
             },
+            Screen::ShuttingDown => {}
+        }
+    }
+
+    /// Rebuilds the device list in place when a partition comes or goes,
+    /// keeping the same device selected if it is still there
+    fn handle_hotplug(&mut self, event: HotplugEvent) {
+        info!(?event, "Hotplug");
+
+        match &event {
+            HotplugEvent::Added(name) => {
+                if !self.remount(name) {
+                    self.automount(name);
+                }
+            }
+            // A share of a pulled drive points at nothing, take it out
+            HotplugEvent::Removed(_) => self.update_shares(),
+        }
+
+        match &self.screen {
+            Screen::Devices(devices, idex) => {
+                let (selected, idex) = (devices.get(*idex).map(|x| x.name.clone()), *idex);
+
+                let devices = match self.list_devices() {
+                    Ok(val) => val,
+                    Err(ex) => {
+                        error!(error = %ex, "Could not list devices");
+                        return;
+                    }
+                };
+
+                let index = selected
+                    .and_then(|name| devices.iter().position(|x| x.name == name))
+                    .unwrap_or_else(|| idex.min(devices.len().saturating_sub(1)));
+
+                self.screen = Screen::Devices(devices, index);
+            }
+            Screen::DeviceDetail(devices, idex, _) => {
+                let (name, idex) = (devices[*idex].name.clone(), *idex);
+                self.device_detail(&name, idex);
+            }
+            _ => {}
         }
     }
 
-    pub fn load_config(&mut self) {
-        if let Some(config) = Config::load() {
-            self.config = config;
+    /// Expires the current notification, returns true if the display needs
+    /// redrawing
+    fn tick(&mut self, now: Instant) -> bool {
+        match &self.notification {
+            Some(Notification { expires, .. }) if now >= *expires => {
+                self.notification = None;
+                true
+            }
+            _ => false,
         }
     }
 
-    fn devices(&mut self, index: Option<usize>) {
-        let devices = match get_devices(&self.config.mount_path) {
+    fn notify(&mut self, msg: String) {
+        self.notification = Some(Notification {
+            msg,
+            expires: Instant::now() + NOTIFICATION_TIME,
+        });
+    }
+
+    fn automount(&mut self, name: &str) {
+        if self.config.automount.mode == AutomountMode::Off {
+            return;
+        }
+
+        let devices = match self.list_devices() {
             Ok(val) => val,
             Err(ex) => {
-                eprintln!("{ex:?}");
-                self.screen = Screen::Error("Could not get devices".to_string());
+                error!(error = %ex, "Could not list devices");
                 return;
             }
         };
 
-        self.screen = Screen::Devices(devices, index.unwrap_or(0));
+        let device = match devices.into_iter().find(|x| x.name == name) {
+            Some(val) => val,
+            None => return,
+        };
+
+        if !automount::should_mount(&self.config.automount, &device) {
+            return;
+        }
+
+        match self.mount(&device) {
+            Ok(_) => {
+                info!(device = %device.name, path = %device.path.display(), "Automounted");
+                self.notify(format!("Mounted {}", device.name));
+                self.update_shares();
+            }
+            Err(ex) => {
+                warn!(device = %device.name, error = %ex, "Could not automount");
+                self.notify(format!("Could not mount {}", device.name));
+            }
+        }
     }
-}
 
-impl Drop for App {
-    fn drop(&mut self) {
-        match get_devices(&self.config.mount_path) {
-            Ok(devices) => {
-                for device in devices {
-                    if device.mounted {
-                        match device.unmount() {
-                            Ok(_) => {}
-                            Err(ex) => {
-                                eprintln!("{ex}")
-                            }
-                        };
-                    }
-                }
+    /// Mounts `name` where it was before if drive-pi had it mounted when it
+    /// last stopped, returning whether it did
+    fn remount(&mut self, name: &str) -> bool {
+        if !self.config.remount_on_start {
+            return false;
+        }
+
+        let devices = match self.list_devices() {
+            Ok(val) => val,
+            Err(ex) => {
+                error!(error = %ex, "Could not list devices");
+                return false;
             }
-            Err(_) => {}
         };
 
-        let subpaths = WalkDir::new(&self.config.mount_path);
+        let Some(device) = devices.into_iter().find(|x| x.name == name && !x.mounted) else {
+            return false;
+        };
+        let Some(mount) = self
+            .state
+            .mounts
+            .iter()
+            .find(|x| x.matches(&device))
+            .cloned()
+        else {
+            return false;
+        };
 
-        for sub in subpaths.into_iter().filter_map(|z| z.ok()) {
-            if sub.path().is_dir() && sub.path() == self.config.mount_path {
-                match fs::remove_dir(sub.path()) {
-                    Ok(_) => {}
-                    Err(ex) => {
-                        eprintln!("Could not delete director {} {}", sub.path().display(), ex)
-                    }
-                };
+        let device = Device {
+            path: mount.path,
+            ..device
+        };
+        match self.mount_with(&device, &mount.options) {
+            Ok(_) => {
+                info!(device = %device.name, path = %device.path.display(), "Remounted after restart");
+                self.notify(format!("Mounted {}", device.name));
+                self.update_shares();
+            }
+            Err(ex) => {
+                warn!(device = %device.name, error = %ex, "Could not remount after restart");
+                self.notify(format!("Could not mount {}", device.name));
             }
         }
-    }
-}
 
-impl<'a> Drawable for App {
-    type Color = BinaryColor;
+        true
+    }
 
-    type Output = ();
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
-    where
-        D: DrawTarget<Color = Self::Color>,
-    {
-        self.screen.draw(target)
+    /// Puts up the shutdown message, to draw while [`App::shutdown`] runs
+    pub fn shutting_down(&mut self) {
+        self.notification = None;
+        self.screen = Screen::ShuttingDown;
     }
-}
 
-enum Screen {
-    Home,
-    Devices(Vec<Device>, usize),
-    Error(String),
-    ConfirmExit,
-}
+    /// Unmounts the devices drive-pi mounted and stops sharing them, leaving
+    /// whatever is left mounted once `deadline` has passed
+    ///
+    /// The mounts stay in the state file, so they can be mounted again on the
+    /// next start if `remount_on_start` is set.
+    pub fn shutdown(&mut self, deadline: Instant) {
+        let started = Instant::now();
 
-impl Default for Screen {
-    fn default() -> Self {
-        Self::Home
-    }
-}
+        if let Err(ex) = self.block.sync() {
+            error!(error = %ex, "Could not sync");
+        }
 
-impl Screen {
-    fn opts(&self) -> [&'static str; 4] {
-        match self {
-            Screen::Home => ["WIFI", "MNT", "SMB", "EXIT"],
-            Screen::Devices(drives, index) => {
-                if drives.is_empty() {
-                    ["", "", "", "BACK"]
-                } else if drives[*index].mounted {
-                    ["^", "UMT", "v", "BACK"]
-                } else {
-                    ["^", "MNT", "v", "BACK"]
+        match self.list_devices() {
+            Ok(devices) => {
+                for device in devices.iter().filter(|x| x.owned) {
+                    if Instant::now() >= deadline {
+                        warn!(device = %device.name, "Out of time, leaving mounted");
+                        continue;
+                    }
+
+                    match self.block.unmount(device) {
+                        Ok(_) => info!(device = %device.name, "Unmounted"),
+                        Err(ex) => error!(device = %device.name, error = %ex, "Could not unmount"),
+                    }
                 }
             }
-            Screen::Error(_) => ["BACK", "", "", ""],
-            Screen::ConfirmExit => ["YES", "NO", "", ""],
+            Err(ex) => error!(error = %ex, "Could not list devices"),
         }
-    }
-}
 
-impl Drawable for Screen {
-    type Color = BinaryColor;
+        // Anything still mounted stops being shared too
+        match smb::update(self.smb.as_mut(), &[], &self.config.smb) {
+            Ok(shares) => {
+                self.state.shares = shares;
+                self.save_state();
+            }
+            Err(ex) => error!(error = %ex, "Could not remove shares"),
+        }
 
-    type Output = ();
+        info!(
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "Shut down"
+        );
+    }
 
-    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
-    where
-        D: DrawTarget<Color = Self::Color>,
-    {
-        boxes(target, self.opts())?;
-        match self {
-            Screen::Home => Ok(()),
-            Screen::Devices(d, hovered) => devices(target, d, *hovered),
-            Screen::Error(msg) => error(target, msg),
-            Screen::ConfirmExit => confirm_exit(target),
+    /// Picks up the state file from the last run, reattaching to its mounts
+    /// and removing any mount points it left behind
+    pub fn load_state(&mut self) {
+        match State::load(&self.config.state_path) {
+            Ok(state) => self.state = state,
+            Err(ex) => {
+                error!(path = %self.config.state_path.display(), error = %ex, "Could not load state");
+                return;
+            }
         }
+
+        self.reattach();
+        self.remove_mount_dirs();
+        self.update_shares();
     }
-}
 
-fn confirm_exit<D>(display: &mut D) -> Result<(), D::Error>
-where
-    D: DrawTarget<Color = BinaryColor>,
-{
-    Text::with_alignment(
-        "Are you sure\nyou want to exit",
-        display.bounding_box().center(),
-        MonoTextStyle::new(&FONT_6X10, BinaryColor::On),
-        Alignment::Center,
-    )
-    .draw(display)?;
+    /// Goes through the mounts from the last run, keeping the ones that are
+    /// still there and forgetting the rest
+    ///
+    /// With `remount_on_start` drives that are plugged in but not mounted,
+    /// such as after a reboot, are mounted again where they were, and ones
+    /// that are not plugged in are kept for when they are.
+    fn reattach(&mut self) {
+        if self.state.mounts.is_empty() {
+            return;
+        }
 
-    Ok(())
-}
+        let devices = match self.list_devices() {
+            Ok(val) => val,
+            Err(ex) => {
+                warn!(error = %ex, "Could not list devices, keeping mounts");
+                return;
+            }
+        };
 
-fn error<D>(display: &mut D, msg: &String) -> Result<(), D::Error>
-where
-    D: DrawTarget<Color = BinaryColor>,
-{
-    let Point { x, .. } = display.bounding_box().center();
-    Text::with_alignment(
-        "Error",
-        Point { x, y: 20 },
-        MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::On),
-        Alignment::Center,
-    )
-    .draw(display)?;
+        for mount in std::mem::take(&mut self.state.mounts) {
+            match devices.iter().find(|x| mount.matches(x)) {
+                Some(device) if device.mounted && device.path == mount.path => {
+                    info!(device = %device.name, path = %mount.path.display(), "Reattached");
+                    self.state.add_mount(mount);
+                }
+                Some(device) if !device.mounted && self.config.remount_on_start => {
+                    let device = Device {
+                        path: mount.path.clone(),
+                        ..device.clone()
+                    };
 
-    Text::with_alignment(
-        msg,
-        Point { x, y: 40 },
-        MonoTextStyle::new(&FONT_5X8, BinaryColor::On),
-        Alignment::Center,
-    )
-    .draw(display)?;
+                    match self.mount_with(&device, &mount.options) {
+                        Ok(_) => {
+                            info!(device = %device.name, path = %device.path.display(), "Remounted after restart")
+                        }
+                        Err(ex) => {
+                            warn!(device = %device.name, error = %ex, "Could not remount after restart")
+                        }
+                    }
+                }
+                None if self.config.remount_on_start => {
+                    debug!(path = %mount.path.display(), "Waiting for drive to remount");
+                    self.state.add_mount(mount);
+                }
+                _ => info!(path = %mount.path.display(), "Forgetting mount"),
+            }
+        }
 
-    Ok(())
-}
+        self.save_state();
+    }
 
-pub fn devices<D>(display: &mut D, devices: &Vec<Device>, hovered: usize) -> Result<(), D::Error>
-where
-    D: DrawTarget<Color = BinaryColor>,
-{
-    let style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
+    fn save_state(&self) {
+        if let Err(ex) = self.state.save(&self.config.state_path) {
+            warn!(path = %self.config.state_path.display(), error = %ex, "Could not save state");
+        }
+    }
 
-    Text::new("NAME", Point { x: 5, y: 18 }, style).draw(display)?;
-    Text::with_alignment("SIZE", Point { x: 70, y: 18 }, style, Alignment::Center).draw(display)?;
-    Text::new("MOUNTED", Point { x: 92, y: 18 }, style).draw(display)?;
+    /// Creates the mount point for `dev` if it is missing, remembering that
+    /// drive-pi made it
+    fn create_mount_dir(&mut self, dev: &Device) -> Result<(), DeviceError> {
+        if dev.path.exists() {
+            return Ok(());
+        }
 
-    if devices.is_empty() {
-        Text::with_alignment(
-            "NO DEVICES",
-            display.bounding_box().center(),
-            style,
-            Alignment::Center,
-        )
-        .draw(display)?;
-    } else {
-        let top_4: Vec<Device> = devices
-            .iter()
-            .skip(hovered)
-            .take(3)
-            .map(|x| x.to_owned())
-            .collect();
+        fs::create_dir_all(&dev.path)?;
+        debug!(path = %dev.path.display(), "Created mount point");
 
-        Text::new(
-            ">",
-            Point { x: 1, y: 30 },
-            MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
-        )
-        .draw(display)?;
+        self.state.created_dirs.insert(dev.path.clone());
+        self.save_state();
 
-        for (
-            index,
-            Device {
-                name,
-                size,
-                mounted,
-                ..
-            },
-        ) in top_4.iter().enumerate()
-        {
-            Text::new(
-                name,
-                Point {
-                    x: 5,
-                    y: 10 * index as i32 + 30,
-                },
-                style,
-            )
-            .draw(display)?;
+        Ok(())
+    }
 
-            Text::with_alignment(
-                size,
-                Point {
-                    x: 70,
-                    y: 10 * index as i32 + 30,
-                },
-                style,
-                Alignment::Center,
-            )
-            .draw(display)?;
+    /// Removes the mount points drive-pi created that nothing is mounted on
+    ///
+    /// Ones that are not empty are kept, something was written into them
+    /// while they were unmounted and it is not ours to delete.
+    fn remove_mount_dirs(&mut self) {
+        if self.state.created_dirs.is_empty() {
+            return;
+        }
 
-            if *mounted {
-                Text::with_alignment(
-                    "*",
-                    Point {
-                        x: 112,
-                        y: 10 * index as i32 + 30,
-                    },
-                    MonoTextStyle::new(&FONT_5X8, BinaryColor::On),
-                    Alignment::Center,
-                )
-                .draw(display)?;
+        let mounted: HashSet<PathBuf> = match self.list_devices() {
+            Ok(devices) => devices
+                .into_iter()
+                .filter(|x| x.mounted)
+                .map(|x| x.path)
+                .collect(),
+            Err(ex) => {
+                warn!(error = %ex, "Could not list devices, keeping mount points");
+                return;
             }
-        }
-    }
+        };
+
+        let before = self.state.created_dirs.len();
+        self.state.created_dirs.retain(|path| {
+            if mounted.contains(path) {
+                return true;
+            }
+
+            match fs::remove_dir(path) {
+                Ok(_) => {
+                    debug!(path = %path.display(), "Removed mount point");
+                    false
+                }
+                Err(ex) if ex.kind() == ErrorKind::NotFound => false,
+                Err(ex) => {
+                    warn!(path = %path.display(), error = %ex, "Keeping mount point");
+                    true
+                }
+            }
+        });
+
+        if self.state.created_dirs.len() != before {
+            self.save_state();
+        }
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Name of the current screen, for checking where scripted input ends up
+    pub fn screen_name(&self) -> &'static str {
+        self.screen.name()
+    }
+
+    fn devices(&mut self, index: Option<usize>) {
+        let devices = match self.list_devices() {
+            Ok(val) => val,
+            Err(ex) => {
+                error!(error = %ex, "Could not list devices");
+                self.show_error(device_error(&ex, None, "Could not get devices".to_string()));
+                return;
+            }
+        };
+
+        self.screen = Screen::Devices(devices, index.unwrap_or(0));
+    }
+
+    fn smb(&mut self, index: Option<usize>) {
+        let devices = match self.list_devices() {
+            Ok(val) => val,
+            Err(ex) => {
+                error!(error = %ex, "Could not list devices");
+                self.show_error(device_error(&ex, None, "Could not get devices".to_string()));
+                return;
+            }
+        };
+
+        let shares = smb::shares(&devices);
+        let index = index.unwrap_or(0).min(shares.len().saturating_sub(1));
+        self.screen = Screen::Smb(shares, index);
+    }
+
+    /// Lists the devices, with paths under the canonical mount root so they
+    /// compare equal to the mount points listed and recorded in the state
+    fn list_devices(&mut self) -> Result<Vec<Device>, DeviceError> {
+        let root = device::mount_root(&self.config.mount_path)?;
+
+        let mut devices = self.block.devices(&root, &self.state.owned_paths())?;
+        naming::assign_paths(
+            &mut devices,
+            &root,
+            &self.config.mount_name,
+            &self.state.created_dirs,
+        );
+
+        Ok(devices)
+    }
+
+    /// Mounts `dev` when asked to from the devices screens, showing what
+    /// went wrong if it could not be, returns whether it was mounted
+    fn mount_selected(&mut self, dev: &Device) -> bool {
+        match self.mount(dev) {
+            Ok(_) => {
+                self.update_shares();
+                true
+            }
+            Err(ex) => {
+                self.show_error(device_error(
+                    &ex,
+                    Some(dev),
+                    format!("Could not mount {}", dev.name),
+                ));
+                false
+            }
+        }
+    }
+
+    fn mount(&mut self, dev: &Device) -> Result<(), DeviceError> {
+        let options = MountOptions::new(&self.config.mount, dev, self.read_only(dev));
+        self.mount_with(dev, &options)
+    }
+
+    /// Mounts `dev` with `options` and records it as owned by drive-pi
+    fn mount_with(&mut self, dev: &Device, options: &MountOptions) -> Result<(), DeviceError> {
+        let started = Instant::now();
+
+        let result = self
+            .create_mount_dir(dev)
+            .and_then(|_| self.block.mount(dev, options));
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        if result.is_ok() {
+            self.state.add_mount(OwnedMount::new(dev, options));
+            self.save_state();
+        }
+
+        match &result {
+            Ok(_) => info!(
+                device = %dev.name,
+                fstype = options.fstype.as_deref().unwrap_or("auto"),
+                options = %options.options.join(","),
+                path = %dev.path.display(),
+                elapsed_ms,
+                "Mounted"
+            ),
+            Err(ex) => error!(
+                device = %dev.name,
+                fstype = options.fstype.as_deref().unwrap_or("auto"),
+                options = %options.options.join(","),
+                elapsed_ms,
+                error = %ex,
+                "Could not mount"
+            ),
+        }
+
+        result
+    }
+
+    /// Shows `msg`, linked to whatever was logged last as that is what went
+    /// wrong
+    fn show_error(&mut self, msg: String) {
+        let related = self.logs.latest();
+
+        warn!(msg = %msg.replace('\n', " "), "Showing error screen");
+        self.screen = Screen::Error(msg, related);
+    }
+
+    /// Shows the recent log entries wrapped to the display, starting at the
+    /// entry with id `entry` or the newest page
+    fn logs(&mut self, entry: Option<u64>) {
+        let layout = Layout::new(self.bounds.get());
+        let width = layout.chars();
+
+        let mut lines = vec![];
+        let mut top = None;
+        for log in self.logs.entries() {
+            if Some(log.id) == entry {
+                top = Some(lines.len());
+            }
+            lines.extend(wrap(&log.to_string(), width));
+        }
+
+        let last_page = lines.len().saturating_sub(layout.lines());
+        let top = top.unwrap_or(last_page).min(last_page);
+        self.screen = Screen::Logs(lines, top);
+    }
+
+    /// Mounted devices report what they are mounted with, otherwise it is
+    /// whatever was picked on the detail screen
+    fn read_only(&self, dev: &Device) -> bool {
+        if dev.mounted {
+            dev.read_only()
+        } else {
+            self.read_only.contains(&dev.name)
+        }
+    }
+
+    /// Shows the detail screen for the device called `name`, falling back to
+    /// the list if it has gone away
+    fn device_detail(&mut self, name: &str, index: usize) {
+        let devices = match self.list_devices() {
+            Ok(val) => val,
+            Err(ex) => {
+                error!(error = %ex, "Could not list devices");
+                self.show_error(device_error(&ex, None, "Could not get devices".to_string()));
+                return;
+            }
+        };
+
+        match devices.iter().position(|x| x.name == name) {
+            Some(index) => {
+                let read_only = self.read_only(&devices[index]);
+                self.screen = Screen::DeviceDetail(devices, index, read_only)
+            }
+            None => {
+                let index = index.min(devices.len().saturating_sub(1));
+                self.screen = Screen::Devices(devices, index);
+            }
+        }
+    }
+
+    /// Syncs, unmounts and optionally powers off `dev`, only reporting it as
+    /// safe to remove once every step has succeeded
+    fn eject(&mut self, dev: &Device) {
+        let started = Instant::now();
+
+        if let Err(ex) = self.block.sync() {
+            error!(device = %dev.name, error = %ex, "Could not sync");
+            self.show_error(device_error(
+                &ex,
+                Some(dev),
+                format!("Could not sync {}", dev.name),
+            ));
+            return;
+        }
+
+        match self.block.unmount(dev) {
+            Ok(_) => info!(
+                device = %dev.name,
+                fstype = dev.fstype.as_deref().unwrap_or("unknown"),
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                "Unmounted"
+            ),
+            Err(DeviceError::Busy(_)) => {
+                let holders = self.block.holders(dev).unwrap_or_else(|ex| {
+                    warn!(device = %dev.name, error = %ex, "Could not get holders");
+                    vec![]
+                });
+
+                warn!(device = %dev.name, ?holders, "Device is busy");
+                self.show_error(format!("{} is busy\n{}", dev.name, holders.join("\n")));
+                return;
+            }
+            Err(ex) => {
+                error!(device = %dev.name, error = %ex, "Could not unmount");
+                self.show_error(device_error(
+                    &ex,
+                    Some(dev),
+                    format!("Could not unmount {}", dev.name),
+                ));
+                return;
+            }
+        }
+
+        self.state.remove_mount(&dev.path);
+        self.save_state();
+        self.remove_mount_dirs();
+        self.update_shares();
+
+        if self.config.power_off_on_eject {
+            if let Err(ex) = self.block.power_off(dev) {
+                error!(device = %dev.name, error = %ex, "Could not power off");
+                self.show_error(device_error(
+                    &ex,
+                    Some(dev),
+                    format!("Could not power off\n{}", dev.name),
+                ));
+                return;
+            }
+            info!(device = %dev.name, "Powered off");
+        }
+
+        self.screen = Screen::Ejected(dev.name.clone());
+    }
+
+    /// Re-exports the currently mounted devices and records the shares,
+    /// called after every mount and unmount
+    fn update_shares(&mut self) {
+        let devices = match self.list_devices() {
+            Ok(val) => val,
+            Err(ex) => {
+                error!(error = %ex, "Could not list devices");
+                return;
+            }
+        };
+
+        match smb::update(self.smb.as_mut(), &devices, &self.config.smb) {
+            Ok(shares) if shares != self.state.shares => {
+                self.state.shares = shares;
+                self.save_state();
+            }
+            Ok(_) => {}
+            Err(ex) => error!(error = %ex, "Could not update shares"),
+        }
+    }
+
+    fn wifi(&mut self, index: Option<usize>) {
+        let state = match self.wifi.state(&self.config.wifi) {
+            Ok(val) => val,
+            Err(ex) => {
+                error!(error = %ex, "Could not get WiFi state");
+                self.show_error("Could not get\nWiFi state".to_string());
+                return;
+            }
+        };
+
+        let networks = match self.wifi.scan(&self.config.wifi) {
+            Ok(val) => val,
+            Err(ex) => {
+                warn!(error = %ex, "Could not scan for networks");
+                vec![]
+            }
+        };
+
+        let index = index.unwrap_or(0).min(networks.len().saturating_sub(1));
+        self.screen = Screen::Wifi(state, networks, index);
+    }
+}
+
+/// What to tell the user about `ex`, with `generic` for failures there is
+/// nothing more useful to say about
+fn device_error(ex: &DeviceError, dev: Option<&Device>, generic: String) -> String {
+    let name = dev.map(|x| x.name.as_str()).unwrap_or("The device");
+
+    match ex {
+        DeviceError::MissingTool(tool) => format!("{tool} is missing\ninstall it first"),
+        DeviceError::Parse(_) => "Could not read\nthe device list".to_string(),
+        DeviceError::PermissionDenied(_) => "Permission denied\nrun drive-pi as root".to_string(),
+        DeviceError::UnknownFilesystem(_) => match dev.and_then(|x| x.fstype.as_deref()) {
+            Some(fstype) => format!("No {fstype} driver\nfor {name}"),
+            None => format!("{name} has an\nunknown filesystem"),
+        },
+        DeviceError::Busy(_) => format!("{name} is busy\nclose its files"),
+        DeviceError::AlreadyMounted(_) => format!("{name} is already\nmounted elsewhere"),
+        DeviceError::DiskInUse(names) => format!("{names} is still\nmounted"),
+        DeviceError::Vanished(_) => format!("{name} was\nunplugged"),
+        DeviceError::Failed(_) | DeviceError::Io(_) => generic,
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        if let Ok(devices) = self.list_devices() {
+            for device in devices {
+                if device.owned {
+                    match self.block.unmount(&device) {
+                        Ok(_) => {}
+                        Err(ex) => {
+                            error!(device = %device.name, error = %ex, "Could not unmount")
+                        }
+                    };
+                }
+            }
+        }
+
+        self.remove_mount_dirs();
+    }
+}
+
+impl Drawable for App {
+    type Color = BinaryColor;
+
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        self.bounds.set(target.bounding_box());
+        self.screen.draw(target)?;
+
+        if let Some(Notification { msg, .. }) = &self.notification {
+            notification(target, msg)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+enum Screen {
+    #[default]
+    Home,
+    Devices(Vec<Device>, usize),
+    /// Devices, selected index and whether the selected device is, or will be,
+    /// mounted read only
+    DeviceDetail(Vec<Device>, usize, bool),
+    Wifi(WifiState, Vec<Network>, usize),
+    Smb(Vec<Share>, usize),
+    Ejected(String),
+    /// Message and the id of the log entry explaining it
+    Error(String, Option<u64>),
+    /// Log entries wrapped to the display and the first line shown
+    Logs(Vec<String>, usize),
+    ConfirmExit,
+    ShuttingDown,
+}
+
+impl Screen {
+    fn name(&self) -> &'static str {
+        match self {
+            Screen::Home => "home",
+            Screen::Devices(..) => "devices",
+            Screen::DeviceDetail(..) => "device_detail",
+            Screen::Wifi(..) => "wifi",
+            Screen::Smb(..) => "smb",
+            Screen::Ejected(_) => "ejected",
+            Screen::Error(..) => "error",
+            Screen::Logs(..) => "logs",
+            Screen::ConfirmExit => "confirm_exit",
+            Screen::ShuttingDown => "shutting_down",
+        }
+    }
+
+    /// Whether `button` scrolls a list on this screen, so it makes sense to
+    /// repeat it while held
+    fn scrolls(&self, button: Button) -> bool {
+        match self {
+            // Scrolls down only, the top left button opens the detail screen
+            Screen::Devices(..) => button == Button::BottomLeft,
+            Screen::Wifi(..) | Screen::Smb(..) | Screen::Logs(..) => {
+                matches!(button, Button::TopLeft | Button::BottomLeft)
+            }
+            _ => false,
+        }
+    }
+
+    fn opts(&self) -> [&'static str; 4] {
+        match self {
+            Screen::Home => ["WIFI", "MNT", "SMB", "EXIT"],
+            Screen::Devices(drives, index) => {
+                if drives.is_empty() {
+                    ["", "", "", "BACK"]
+                } else if drives[*index].mounted {
+                    ["INFO", "EJCT", "v", "BACK"]
+                } else {
+                    ["INFO", "MNT", "v", "BACK"]
+                }
+            }
+            Screen::DeviceDetail(drives, index, read_only) => {
+                let toggle = if *read_only { "RW" } else { "RO" };
+                if drives[*index].mounted {
+                    ["EJCT", toggle, "", "BACK"]
+                } else {
+                    ["MNT", toggle, "", "BACK"]
+                }
+            }
+            Screen::Wifi(state, networks, _) => {
+                let toggle = match state {
+                    WifiState::Hotspot(_) => "OFF",
+                    _ => "AP",
+                };
+
+                if networks.is_empty() {
+                    ["", toggle, "", "BACK"]
+                } else {
+                    ["^", toggle, "v", "BACK"]
+                }
+            }
+            Screen::Smb(shares, _) => {
+                if shares.is_empty() {
+                    ["", "PASS", "", "BACK"]
+                } else {
+                    ["^", "PASS", "v", "BACK"]
+                }
+            }
+            Screen::Ejected(_) => ["OK", "", "", ""],
+            Screen::Error(..) => ["BACK", "DETAIL", "", ""],
+            Screen::Logs(lines, _) => {
+                if lines.is_empty() {
+                    ["", "", "", "BACK"]
+                } else {
+                    ["^", "", "v", "BACK"]
+                }
+            }
+            Screen::ConfirmExit => ["YES", "NO", "LOGS", ""],
+            Screen::ShuttingDown => ["", "", "", ""],
+        }
+    }
+}
+
+impl Drawable for Screen {
+    type Color = BinaryColor;
+
+    type Output = ();
+
+    fn draw<D>(&self, target: &mut D) -> Result<Self::Output, D::Error>
+    where
+        D: DrawTarget<Color = Self::Color>,
+    {
+        let layout = Layout::new(target.bounding_box());
+
+        boxes(target, &layout, self.opts())?;
+        match self {
+            Screen::Home => Ok(()),
+            Screen::Devices(d, hovered) => devices(target, &layout, d, *hovered),
+            Screen::DeviceDetail(d, index, read_only) => {
+                device_detail(target, &layout, &d[*index], *read_only)
+            }
+            Screen::Wifi(state, networks, hovered) => {
+                wifi(target, &layout, state, networks, *hovered)
+            }
+            Screen::Smb(shares, hovered) => smb(target, &layout, shares, *hovered),
+            Screen::Ejected(name) => ejected(target, &layout, name),
+            Screen::Error(msg, _) => error(target, &layout, msg),
+            Screen::Logs(lines, top) => logs(target, &layout, lines, *top),
+            Screen::ConfirmExit => confirm_exit(target, &layout),
+            Screen::ShuttingDown => shutting_down(target, &layout),
+        }
+    }
+}
+
+/// Where screens put their content, worked out from the display size
+///
+/// Tall panels get a title bar with the content between the top and bottom
+/// button rows. On 128x32 panels there is no room for that, so the title is
+/// dropped and the content sits between the left and right buttons instead.
+struct Layout {
+    bounds: Rectangle,
+    content: Rectangle,
+    compact: bool,
+}
+
+impl Layout {
+    const LINE_HEIGHT: i32 = 10;
+
+    fn new(bounds: Rectangle) -> Self {
+        let Size { width, height } = bounds.size;
+        let compact = height < 64;
+
+        let content = if compact {
+            Rectangle::new(
+                bounds.top_left + Point::new(26, 0),
+                Size::new(width.saturating_sub(52), height),
+            )
+        } else {
+            Rectangle::new(
+                bounds.top_left + Point::new(0, 12),
+                Size::new(width, height.saturating_sub(24)),
+            )
+        };
+
+        Self {
+            bounds,
+            content,
+            compact,
+        }
+    }
+
+    fn left(&self) -> i32 {
+        self.content.top_left.x + 5
+    }
+
+    fn right(&self) -> i32 {
+        self.content.top_left.x + self.content.size.width as i32
+    }
+
+    fn center_x(&self) -> i32 {
+        self.content.center().x
+    }
+
+    /// Baseline of the nth line of 5x8 text in the content area
+    fn line(&self, index: usize) -> i32 {
+        self.content.top_left.y + 8 + Self::LINE_HEIGHT * index as i32
+    }
+
+    fn lines(&self) -> usize {
+        (self.content.size.height as i32 / Self::LINE_HEIGHT) as usize
+    }
+
+    /// How many 5x8 characters fit on a line
+    fn chars(&self) -> usize {
+        ((self.right() - self.left()) / 5).max(1) as usize
+    }
+
+    /// Font for the centred messages, the 6x10 one does not fit between the
+    /// buttons of a compact layout
+    fn message_style(&self) -> MonoTextStyle<'static, BinaryColor> {
+        if self.compact {
+            MonoTextStyle::new(&FONT_5X8, BinaryColor::On)
+        } else {
+            MonoTextStyle::new(&FONT_6X10, BinaryColor::On)
+        }
+    }
+
+    /// Draws the hover marker in front of the nth line
+    fn marker<D>(&self, display: &mut D, index: usize) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        Text::new(
+            ">",
+            Point {
+                x: self.left() - 4,
+                y: self.line(index),
+            },
+            MonoTextStyle::new(&FONT_4X6, BinaryColor::On),
+        )
+        .draw(display)?;
+
+        Ok(())
+    }
+
+    /// Draws `msg` centred in the content area, for empty lists
+    fn empty<D>(&self, display: &mut D, msg: &str) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        Text::with_alignment(
+            msg,
+            Point {
+                x: self.center_x(),
+                y: self.line(self.lines() / 2),
+            },
+            MonoTextStyle::new(&FONT_5X8, BinaryColor::On),
+            Alignment::Center,
+        )
+        .draw(display)?;
+
+        Ok(())
+    }
+}
+
+fn notification<D>(display: &mut D, msg: &str) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let bounds = display.bounding_box();
+    let center = bounds.center();
+
+    Rectangle::with_center(center, Size::new(108.min(bounds.size.width - 4), 22))
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .fill_color(BinaryColor::Off)
+                .stroke_color(BinaryColor::On)
+                .stroke_width(1)
+                .build(),
+        )
+        .draw(display)?;
+
+    Text::with_alignment(
+        msg,
+        center + Point::new(0, 3),
+        MonoTextStyle::new(&FONT_5X8, BinaryColor::On),
+        Alignment::Center,
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+fn confirm_exit<D>(display: &mut D, layout: &Layout) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    Text::with_alignment(
+        if layout.compact {
+            "Are you\nsure you\nwant to exit"
+        } else {
+            "Are you sure\nyou want to exit"
+        },
+        layout.content.center(),
+        layout.message_style(),
+        Alignment::Center,
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+fn shutting_down<D>(display: &mut D, layout: &Layout) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    Text::with_alignment(
+        "Shutting down",
+        layout.content.center(),
+        layout.message_style(),
+        Alignment::Center,
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+fn ejected<D>(display: &mut D, layout: &Layout, name: &str) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    Text::with_alignment(
+        &format!("Safe to remove\n{name}"),
+        layout.content.center(),
+        layout.message_style(),
+        Alignment::Center,
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+fn error<D>(display: &mut D, layout: &Layout, msg: &str) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let x = layout.center_x();
+
+    Text::with_text_style(
+        "Error",
+        Point {
+            x,
+            y: layout.content.top_left.y,
+        },
+        MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::On),
+        TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Top)
+            .build(),
+    )
+    .draw(display)?;
+
+    Text::with_alignment(
+        msg,
+        Point {
+            x,
+            y: layout.line(2),
+        },
+        MonoTextStyle::new(&FONT_5X8, BinaryColor::On),
+        Alignment::Center,
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+fn logs<D>(display: &mut D, layout: &Layout, lines: &[String], top: usize) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    if lines.is_empty() {
+        return layout.empty(display, "NO LOGS");
+    }
+
+    let style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
+    for (index, line) in lines.iter().skip(top).take(layout.lines()).enumerate() {
+        Text::new(line, Point::new(layout.left(), layout.line(index)), style).draw(display)?;
+    }
+
+    Ok(())
+}
+
+/// Splits `text` into lines of at most `width` characters, breaking at
+/// spaces where it can and through words that are longer than a line
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+
+        if !line.is_empty() && line.chars().count() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+
+        while word.len() > width {
+            let rest = word.split_off(width);
+            lines.push(word.into_iter().collect());
+            word = rest;
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.extend(word);
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+fn devices<D>(
+    display: &mut D,
+    layout: &Layout,
+    devices: &[Device],
+    hovered: usize,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
+    let left = layout.left();
+    let right = layout.right();
+
+    // The size column only fits on full width layouts
+    let size_x = (!layout.compact).then_some(right - 58);
+
+    Text::new("NAME", Point::new(left, layout.line(0)), style).draw(display)?;
+    if let Some(x) = size_x {
+        Text::with_alignment(
+            "SIZE",
+            Point::new(x, layout.line(0)),
+            style,
+            Alignment::Center,
+        )
+        .draw(display)?;
+    }
+    Text::with_alignment(
+        if layout.compact { "MNT" } else { "MOUNTED" },
+        Point::new(right - 1, layout.line(0)),
+        style,
+        Alignment::Right,
+    )
+    .draw(display)?;
+
+    if devices.is_empty() {
+        return layout.empty(display, "NO DEVICES");
+    }
+
+    layout.marker(display, 1)?;
+
+    let rows = layout.lines() - 1;
+    for (index, device) in devices.iter().skip(hovered).take(rows).enumerate() {
+        let Device { size, mounted, .. } = device;
+        let y = layout.line(index + 1);
+
+        Text::new(device.display_name(), Point::new(left, y), style).draw(display)?;
+
+        if let Some(x) = size_x {
+            Text::with_alignment(size, Point::new(x, y), style, Alignment::Center).draw(display)?;
+        }
+
+        if *mounted {
+            Text::with_alignment(
+                if device.read_only() { "RO" } else { "RW" },
+                Point::new(right - 16, y),
+                style,
+                Alignment::Center,
+            )
+            .draw(display)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn smb<D>(
+    display: &mut D,
+    layout: &Layout,
+    shares: &[Share],
+    hovered: usize,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
+    let left = layout.left();
+    let right = layout.right();
+
+    Text::new("SHARE", Point::new(left, layout.line(0)), style).draw(display)?;
+    Text::with_alignment(
+        "STATE",
+        Point::new(right - 1, layout.line(0)),
+        style,
+        Alignment::Right,
+    )
+    .draw(display)?;
+
+    if shares.is_empty() {
+        return layout.empty(display, "NO DEVICES");
+    }
+
+    layout.marker(display, 1)?;
+
+    let rows = layout.lines() - 1;
+    for (index, Share { name, shared, .. }) in shares.iter().skip(hovered).take(rows).enumerate() {
+        let y = layout.line(index + 1);
+
+        Text::new(name, Point::new(left, y), style).draw(display)?;
+
+        Text::with_alignment(
+            if *shared { "ON" } else { "OFF" },
+            Point::new(right - 1, y),
+            style,
+            Alignment::Right,
+        )
+        .draw(display)?;
+    }
+
+    Ok(())
+}
+
+fn wifi<D>(
+    display: &mut D,
+    layout: &Layout,
+    state: &WifiState,
+    networks: &[Network],
+    hovered: usize,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
+    let left = layout.left();
+
+    let status = match state {
+        WifiState::Disconnected => "Disconnected".to_string(),
+        WifiState::Connected(name) => format!("Conn: {name}"),
+        WifiState::Hotspot(ssid) => format!("AP: {ssid}"),
+    };
+
+    Text::new(&status, Point::new(left, layout.line(0)), style).draw(display)?;
+
+    if networks.is_empty() {
+        return layout.empty(display, "NO NETWORKS");
+    }
+
+    layout.marker(display, 1)?;
+
+    let rows = layout.lines() - 1;
+    for (index, Network { ssid, signal, .. }) in
+        networks.iter().skip(hovered).take(rows).enumerate()
+    {
+        let y = layout.line(index + 1);
+
+        Text::new(ssid, Point::new(left, y), style).draw(display)?;
+
+        Text::with_alignment(
+            &format!("{signal}%"),
+            Point::new(layout.right() - 5, y),
+            style,
+            Alignment::Right,
+        )
+        .draw(display)?;
+    }
+
+    Ok(())
+}
+
+fn device_detail<D>(
+    display: &mut D,
+    layout: &Layout,
+    device: &Device,
+    read_only: bool,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
+    let left = layout.left();
+
+    let mode = if read_only { "RO" } else { "RW" };
+    let title = match &device.fstype {
+        Some(fstype) => format!("{} {fstype} {mode}", device.display_name()),
+        None => format!("{} {mode}", device.display_name()),
+    };
+    Text::new(&title, Point::new(left, layout.line(0)), style).draw(display)?;
+
+    // Compact layouts only have room for the title and the usage
+    let mut last_line = 0;
+    if !layout.compact {
+        let model = match &device.model {
+            Some(model) => format!("{} {}", device.size, model.trim()),
+            None => device.size.clone(),
+        };
+        Text::new(&model, Point::new(left, layout.line(1)), style).draw(display)?;
+
+        if let Some(uuid) = &device.uuid {
+            Text::new(uuid, Point::new(left, layout.line(2)), style).draw(display)?;
+        }
+
+        last_line = 2;
+    }
+
+    // Usage bar
+    let bar = Rectangle::new(
+        Point::new(left, layout.line(last_line) + 3),
+        Size::new((layout.right() - left - 5).max(0) as u32, 6),
+    );
+    bar.into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(display)?;
+
+    if let Some(used) = device.fsuse {
+        let filled = bar.size.width.saturating_sub(2) * used.min(100) as u32 / 100;
+        Rectangle::new(bar.top_left + Point::new(1, 1), Size::new(filled, 4))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(display)?;
+    }
+
+    let usage = match (&device.fsused, &device.fsavail) {
+        (Some(used), Some(avail)) if device.mounted => format!("{used}/{avail} free"),
+        _ => "not mounted".to_string(),
+    };
+    Text::with_alignment(
+        &usage,
+        Point {
+            x: layout.center_x(),
+            y: layout.bounds.size.height as i32 - 4,
+        },
+        style,
+        Alignment::Center,
+    )
+    .draw(display)?;
 
     Ok(())
 }
 
-fn boxes<D>(display: &mut D, opts: [&'static str; 4]) -> Result<(), D::Error>
+/// The outline around a button label, widened past the usual four letters
+/// for longer ones
+fn box_size(label: &str) -> Size {
+    Size {
+        height: 12,
+        width: (label.len() as u32 * 5 + 5).max(25),
+    }
+}
+
+fn boxes<D>(display: &mut D, layout: &Layout, opts: [&'static str; 4]) -> Result<(), D::Error>
 where
     D: DrawTarget<Color = BinaryColor>,
 {
     let Rectangle {
         size: Size { width, height },
         ..
-    } = display.bounding_box();
+    } = layout.bounds;
 
     let text_style = MonoTextStyle::new(&FONT_5X8, BinaryColor::On);
 
@@ -385,53 +1730,52 @@ where
     )
     .draw(display)?;
 
-    let rec_size = Size {
-        height: 12,
-        width: 25,
-    };
-
     let stroke = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
 
     // Top left outline
-    Rectangle::new(Point { x: 0, y: 0 }, rec_size)
+    Rectangle::new(Point { x: 0, y: 0 }, box_size(opts[0]))
         .into_styled(stroke)
         .draw(display)?;
     // Top right outline
     Rectangle::new(
         Point {
-            x: 0,
-            y: height as i32 - 12,
+            x: width as i32 - box_size(opts[1]).width as i32,
+            y: 0,
         },
-        rec_size,
+        box_size(opts[1]),
     )
     .into_styled(stroke)
     .draw(display)?;
     // Bottom left outline
     Rectangle::new(
         Point {
-            x: width as i32 - 25,
-            y: 0,
+            x: 0,
+            y: height as i32 - 12,
         },
-        rec_size,
+        box_size(opts[2]),
     )
     .into_styled(stroke)
     .draw(display)?;
     // Bottom right outline
     Rectangle::new(
         Point {
-            x: width as i32 - 25,
+            x: width as i32 - box_size(opts[3]).width as i32,
             y: height as i32 - 12,
         },
-        rec_size,
+        box_size(opts[3]),
     )
     .into_styled(stroke)
     .draw(display)?;
 
+    if layout.compact {
+        return Ok(());
+    }
+
     let lrg = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
     Text::with_alignment(
         "DrivePi",
         Point {
-            x: display.bounding_box().center().x,
+            x: layout.center_x(),
             y: 8,
         },
         lrg,
@@ -441,3 +1785,910 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::BTreeSet, path::Path, rc::Rc};
+
+    use super::*;
+    use crate::{
+        config::{AutomountConfig, DeviceMatch},
+        device::FakeBlockDevices,
+        framebuffer::FrameBuffer,
+        hotplug::{FakeHotplug, HotplugSource},
+        smb::FakeSmb,
+        tempdir::TempDir,
+        wifi::FakeWifi,
+    };
+
+    /// An app on the fake backends, with handles on them
+    struct Harness {
+        app: App,
+        block: Rc<RefCell<FakeBlockDevices>>,
+        smb: Rc<RefCell<FakeSmb>>,
+        wifi: Rc<RefCell<FakeWifi>>,
+        // Last, so the app has tidied up before the directory goes
+        _dir: TempDir,
+    }
+
+    impl Harness {
+        fn new(name: &str) -> Self {
+            let dir = TempDir::new(name).unwrap();
+
+            let block = Rc::new(RefCell::new(FakeBlockDevices::default()));
+            let smb = Rc::new(RefCell::new(FakeSmb::default()));
+            let wifi = Rc::new(RefCell::new(FakeWifi::default()));
+            let mut app = App::with_backends(
+                Box::new(block.clone()),
+                Box::new(wifi.clone()),
+                Box::new(smb.clone()),
+            );
+            app.config.mount_path = dir.path().join("mnt");
+            app.config.state_path = dir.path().join("state.json");
+            app.config.smb.conf_path = dir.path().join("smb.conf");
+
+            Self {
+                app,
+                block,
+                smb,
+                wifi,
+                _dir: dir,
+            }
+        }
+
+        fn press(&mut self, buttons: &[Button]) {
+            for button in buttons {
+                self.app.handle_event(Event::Press(*button));
+            }
+        }
+
+        /// Opens the detail screen of the first device and mounts it
+        fn mount_first(&mut self) {
+            self.press(&[Button::TopRight, Button::TopLeft, Button::TopLeft]);
+        }
+
+        /// Passes on what `hotplug` saw to the app
+        fn hotplug(&mut self, hotplug: &mut FakeHotplug) {
+            for event in hotplug.poll().unwrap() {
+                self.app.handle_event(Event::Hotplug(event));
+            }
+        }
+
+        /// Names on the devices screen and the selected one
+        fn device_list(&self) -> (Vec<String>, String) {
+            match &self.app.screen {
+                Screen::Devices(devices, index) => (
+                    devices.iter().map(|x| x.name.clone()).collect(),
+                    devices[*index].name.clone(),
+                ),
+                _ => panic!("expected devices, found {}", self.app.screen_name()),
+            }
+        }
+
+        /// The smb.conf fragment last written
+        fn smb_conf(&self) -> String {
+            let smb = self.smb.borrow();
+            let (path, contents) = smb.configs.last().expect("no smb.conf written");
+            assert_eq!(path, &self.app.config.smb.conf_path);
+
+            contents.clone()
+        }
+
+        fn mounted(&self, name: &str) -> bool {
+            self.block
+                .borrow()
+                .partitions
+                .iter()
+                .any(|x| x.name == name && x.mounted)
+        }
+
+        fn mount_path(&self, name: &str) -> PathBuf {
+            device::mount_root(&self.app.config.mount_path)
+                .unwrap()
+                .join(name)
+        }
+
+        /// Leaves `name` mounted at `path`, as a previous run or the system
+        /// would have
+        fn mounted_at(&mut self, name: &str, uuid: Option<&str>, path: &Path) {
+            fs::create_dir_all(path).unwrap();
+            self.block.borrow_mut().partitions.push(Device {
+                name: name.to_string(),
+                uuid: uuid.map(str::to_string),
+                mounted: true,
+                path: path.to_path_buf(),
+                ..Default::default()
+            });
+        }
+
+        /// Writes `state` where the app looks for it and loads it, like a
+        /// start after a crash
+        fn start_with(&mut self, state: &State) {
+            state.save(&self.app.config.state_path).unwrap();
+            self.app.load_state();
+        }
+    }
+
+    #[test]
+    fn mounts_from_the_detail_screen() {
+        let mut h = Harness::new("mount");
+        h.block.borrow_mut().plug("sda1", "8G");
+
+        h.mount_first();
+
+        assert!(h.mounted("sda1"));
+        assert_eq!(h.app.screen_name(), "device_detail");
+        assert!(h.mount_path("sda1").is_dir());
+        assert_eq!(h.app.state.owned_paths(), vec![h.mount_path("sda1")]);
+        assert_eq!(
+            State::load(&h.app.config.state_path).unwrap(),
+            h.app.state,
+            "state is saved as it changes"
+        );
+    }
+
+    #[test]
+    fn mounts_and_ejects_from_the_list() {
+        let mut h = Harness::new("mount-list");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.block.borrow_mut().plug("sdb1", "8G");
+
+        h.press(&[Button::TopRight, Button::BottomLeft, Button::TopRight]);
+
+        assert!(h.mounted("sdb1"));
+        assert!(!h.mounted("sda1"));
+        assert_eq!(h.device_list().1, "sdb1");
+        assert_eq!(h.app.screen.opts(), ["INFO", "EJCT", "v", "BACK"]);
+        assert!(h.smb_conf().contains("[sdb1]"));
+
+        h.press(&[Button::TopRight]);
+
+        assert!(!h.mounted("sdb1"));
+        assert!(matches!(&h.app.screen, Screen::Ejected(name) if name == "sdb1"));
+        assert!(h.app.state.mounts.is_empty());
+    }
+
+    #[test]
+    fn info_opens_the_detail_screen() {
+        let mut h = Harness::new("detail");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.block.borrow_mut().plug("sdb1", "8G");
+
+        h.press(&[Button::TopRight, Button::BottomLeft, Button::TopLeft]);
+
+        assert!(
+            matches!(&h.app.screen, Screen::DeviceDetail(devices, 1, false) if devices[1].name == "sdb1")
+        );
+        assert!(!h.mounted("sdb1"));
+
+        h.press(&[Button::BottomRight]);
+        assert_eq!(h.device_list().1, "sdb1");
+    }
+
+    #[test]
+    fn same_label_devices_keep_their_paths() {
+        let mut h = Harness::new("same-label");
+        h.app.config.mount_name = "label".to_string();
+        for name in ["sda1", "sdb1"] {
+            h.block.borrow_mut().partitions.push(Device {
+                name: name.to_string(),
+                label: Some("STICK".to_string()),
+                ..Default::default()
+            });
+        }
+        let paths = |h: &mut Harness| -> Vec<PathBuf> {
+            h.app
+                .list_devices()
+                .unwrap()
+                .into_iter()
+                .map(|x| x.path)
+                .collect()
+        };
+
+        let before = paths(&mut h);
+        assert_eq!(before, vec![h.mount_path("STICK"), h.mount_path("STICK-2")]);
+
+        // Mount sdb1, the second one in the list
+        h.press(&[Button::TopRight, Button::BottomLeft, Button::TopRight]);
+
+        assert!(h.mounted("sdb1"));
+        assert_eq!(paths(&mut h), before);
+    }
+
+    #[test]
+    fn failed_mount_shows_an_error() {
+        let mut h = Harness::new("mount-fail");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.block.borrow_mut().fail_mount.push("sda1".to_string());
+
+        h.mount_first();
+
+        assert!(!h.mounted("sda1"));
+        assert_eq!(h.app.screen_name(), "error");
+        assert_eq!(h.app.screen.opts(), ["BACK", "DETAIL", "", ""]);
+        assert!(h.app.state.mounts.is_empty());
+
+        h.press(&[Button::TopRight]);
+        assert_eq!(h.app.screen_name(), "logs");
+    }
+
+    #[test]
+    fn logs_open_from_the_exit_screen() {
+        let mut h = Harness::new("exit-logs");
+
+        h.press(&[Button::BottomRight, Button::BottomLeft]);
+
+        assert_eq!(h.app.screen_name(), "logs");
+        assert!(!h.app.should_quit);
+
+        h.press(&[Button::BottomRight]);
+        assert_eq!(h.app.screen_name(), "home");
+    }
+
+    #[test]
+    fn eject_unmounts_and_removes_the_mount_point() {
+        let mut h = Harness::new("eject");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.mount_first();
+
+        h.press(&[Button::TopLeft]);
+
+        assert!(!h.mounted("sda1"));
+        assert!(matches!(&h.app.screen, Screen::Ejected(name) if name == "sda1"));
+        assert!(h.block.borrow().syncs > 0);
+        assert!(!h.mount_path("sda1").exists());
+        assert!(h.app.state.mounts.is_empty());
+        assert!(h.app.state.created_dirs.is_empty());
+    }
+
+    #[test]
+    fn busy_eject_names_the_holders() {
+        let mut h = Harness::new("busy");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.mount_first();
+        h.block
+            .borrow_mut()
+            .busy
+            .push(("sda1".to_string(), vec!["bash (42)".to_string()]));
+
+        h.press(&[Button::TopLeft]);
+
+        assert!(h.mounted("sda1"));
+        match &h.app.screen {
+            Screen::Error(msg, _) => {
+                assert!(msg.contains("busy"), "{msg}");
+                assert!(msg.contains("bash (42)"), "{msg}");
+            }
+            _ => panic!("expected an error, found {}", h.app.screen_name()),
+        }
+        assert_eq!(h.app.state.mounts.len(), 1);
+    }
+
+    #[test]
+    fn shutdown_leaves_system_mounts_alone() {
+        let mut h = Harness::new("shutdown");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.mount_first();
+
+        let system = h.mount_path("system");
+        h.block.borrow_mut().partitions.push(Device {
+            name: "sdb1".to_string(),
+            mounted: true,
+            path: system,
+            ..Default::default()
+        });
+
+        h.app.shutdown(Instant::now() + Duration::from_secs(10));
+
+        assert!(!h.mounted("sda1"));
+        assert!(h.mounted("sdb1"));
+        // Kept so it can be mounted again on the next start
+        assert_eq!(h.app.state.owned_paths(), vec![h.mount_path("sda1")]);
+    }
+
+    #[test]
+    fn wifi_toggles_the_hotspot() {
+        let mut h = Harness::new("wifi");
+        h.app.config.wifi.ssid = "drive-pi".to_string();
+        h.wifi.borrow_mut().networks = vec![Network {
+            ssid: "Home".to_string(),
+            signal: 80,
+            secured: true,
+        }];
+
+        h.press(&[Button::TopLeft]);
+        assert!(matches!(
+            &h.app.screen,
+            Screen::Wifi(WifiState::Disconnected, networks, 0) if networks.len() == 1
+        ));
+
+        h.press(&[Button::TopRight]);
+        let hotspot = WifiState::Hotspot("drive-pi".to_string());
+        assert_eq!(h.wifi.borrow().state, Some(hotspot.clone()));
+        assert!(matches!(&h.app.screen, Screen::Wifi(state, _, 0) if *state == hotspot));
+
+        h.press(&[Button::TopRight]);
+        assert_eq!(h.wifi.borrow().state, Some(WifiState::Disconnected));
+        assert!(matches!(
+            &h.app.screen,
+            Screen::Wifi(WifiState::Disconnected, _, 0)
+        ));
+    }
+
+    #[test]
+    fn wifi_failures_show_an_error() {
+        let mut h = Harness::new("wifi-fail");
+
+        h.press(&[Button::TopLeft]);
+        h.wifi.borrow_mut().fail = true;
+        h.press(&[Button::TopRight]);
+
+        assert!(matches!(&h.app.screen, Screen::Error(msg, _) if msg.contains("hotspot")));
+        assert_eq!(h.wifi.borrow().state, None);
+
+        // BACK to Home and into the WiFi screen again
+        h.press(&[Button::TopLeft, Button::TopLeft]);
+        assert!(matches!(&h.app.screen, Screen::Error(msg, _) if msg.contains("WiFi state")));
+    }
+
+    #[test]
+    fn repeat_only_scrolls_lists() {
+        let mut h = Harness::new("repeat");
+        for name in ["sda1", "sdb1", "sdc1"] {
+            h.block.borrow_mut().plug(name, "8G");
+        }
+
+        // Not a list, so holding a button does nothing more
+        assert!(!h.app.handle_event(Event::Repeat(Button::TopRight)));
+        assert_eq!(h.app.screen_name(), "home");
+
+        h.press(&[Button::TopRight]);
+        assert!(h.app.handle_event(Event::Repeat(Button::BottomLeft)));
+        assert!(h.app.handle_event(Event::Repeat(Button::BottomLeft)));
+        assert_eq!(h.device_list().1, "sdc1");
+
+        // Only the scrolling buttons repeat on a list, here that is only down
+        assert!(!h.app.handle_event(Event::Repeat(Button::TopLeft)));
+        assert!(!h.app.handle_event(Event::Repeat(Button::TopRight)));
+        assert!(!h.app.handle_event(Event::Repeat(Button::BottomRight)));
+        assert_eq!(h.device_list().1, "sdc1");
+        assert!(!h.mounted("sdc1"));
+    }
+
+    #[test]
+    fn hotplug_automounts_what_the_rules_allow() {
+        let mut h = Harness::new("automount");
+        h.app.config.automount = AutomountConfig {
+            mode: AutomountMode::Labels,
+            allow: vec![DeviceMatch {
+                label: Some("BACKUP".to_string()),
+                ..Default::default()
+            }],
+            deny: vec![],
+        };
+        let mut hotplug = FakeHotplug::new(h.block.clone());
+
+        for (name, label) in [("sda1", "BACKUP"), ("sdb1", "CAMERA")] {
+            h.block.borrow_mut().partitions.push(Device {
+                name: name.to_string(),
+                label: Some(label.to_string()),
+                ..Default::default()
+            });
+        }
+        h.hotplug(&mut hotplug);
+
+        assert!(h.mounted("sda1"));
+        assert!(!h.mounted("sdb1"));
+        assert_eq!(h.app.state.owned_paths().len(), 1);
+        assert!(h.smb_conf().contains("[sda1]"), "{}", h.smb_conf());
+    }
+
+    #[test]
+    fn hotplug_does_not_automount_when_off() {
+        let mut h = Harness::new("automount-off");
+        let mut hotplug = FakeHotplug::new(h.block.clone());
+
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.hotplug(&mut hotplug);
+
+        assert!(!h.mounted("sda1"));
+        assert!(h.app.state.mounts.is_empty());
+    }
+
+    #[test]
+    fn start_removes_leftover_mount_points() {
+        let mut h = Harness::new("leftover-dirs");
+        let (ours, used, full, system) = (
+            h.mount_path("sda1"),
+            h.mount_path("sdb1"),
+            h.mount_path("sdc1"),
+            h.mount_path("system"),
+        );
+        h.mounted_at("sdb1", None, &used);
+        fs::create_dir_all(&ours).unwrap();
+        fs::create_dir_all(&full).unwrap();
+        fs::write(full.join("notes.txt"), "written while unmounted").unwrap();
+        fs::create_dir_all(&system).unwrap();
+
+        h.start_with(&State {
+            created_dirs: BTreeSet::from([ours.clone(), used.clone(), full.clone()]),
+            ..Default::default()
+        });
+
+        // Only the empty, unmounted one drive-pi made goes
+        assert!(!ours.exists());
+        assert!(used.is_dir());
+        assert!(full.join("notes.txt").is_file());
+        assert!(system.is_dir());
+        assert_eq!(h.app.state.created_dirs, BTreeSet::from([used, full]));
+        assert_eq!(State::load(&h.app.config.state_path).unwrap(), h.app.state);
+    }
+
+    #[test]
+    fn drop_removes_only_its_own_mount_points() {
+        let mut h = Harness::new("drop-dirs");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.mount_first();
+        let (ours, system) = (h.mount_path("sda1"), h.mount_path("system"));
+        h.mounted_at("sdb1", None, &system);
+        let block = h.block.clone();
+        let state_path = h.app.config.state_path.clone();
+
+        drop(h.app);
+
+        assert!(!ours.exists());
+        assert!(system.is_dir());
+        assert!(block
+            .borrow()
+            .partitions
+            .iter()
+            .any(|x| x.name == "sdb1" && x.mounted));
+        assert!(State::load(&state_path).unwrap().created_dirs.is_empty());
+    }
+
+    fn owned(name: &str, uuid: Option<&str>, path: &Path) -> OwnedMount {
+        OwnedMount {
+            name: name.to_string(),
+            uuid: uuid.map(str::to_string),
+            path: path.to_path_buf(),
+            options: MountOptions {
+                fstype: Some("vfat".to_string()),
+                options: vec!["ro".to_string(), "noatime".to_string()],
+            },
+        }
+    }
+
+    #[test]
+    fn start_reattaches_by_uuid() {
+        let mut h = Harness::new("reattach-uuid");
+        let path = h.mount_path("sdb1");
+        // The kernel name changed since, the UUID still matches
+        h.mounted_at("sdc1", Some("1234-ABCD"), &path);
+
+        h.start_with(&State {
+            mounts: vec![owned("sdb1", Some("1234-ABCD"), &path)],
+            ..Default::default()
+        });
+
+        assert_eq!(h.app.state.owned_paths(), vec![path.clone()]);
+        assert!(h.app.list_devices().unwrap()[0].owned);
+        assert!(h.smb_conf().contains(&path.display().to_string()));
+        assert_eq!(h.app.state.shares.len(), 1);
+    }
+
+    #[test]
+    fn start_reattaches_by_name_without_a_uuid() {
+        let mut h = Harness::new("reattach-name");
+        let (ours, theirs) = (h.mount_path("sda1"), h.mount_path("sdb1"));
+        h.mounted_at("sda1", None, &ours);
+        // Same kernel name, but another drive
+        h.mounted_at("sdb1", Some("5678-EF01"), &theirs);
+
+        h.start_with(&State {
+            mounts: vec![
+                owned("sda1", None, &ours),
+                owned("sdb1", Some("1234-ABCD"), &theirs),
+            ],
+            ..Default::default()
+        });
+
+        assert_eq!(h.app.state.owned_paths(), vec![ours]);
+        let devices = h.app.list_devices().unwrap();
+        assert!(devices[0].owned);
+        assert!(!devices[1].owned);
+        assert!(h.mounted("sdb1"), "not ours to unmount");
+    }
+
+    #[test]
+    fn start_forgets_mounts_that_are_gone() {
+        let mut h = Harness::new("reattach-gone");
+        let (unmounted, vanished) = (h.mount_path("sda1"), h.mount_path("sdb1"));
+        h.block.borrow_mut().plug("sda1", "8G");
+
+        h.start_with(&State {
+            mounts: vec![
+                owned("sda1", None, &unmounted),
+                owned("sdb1", Some("1234-ABCD"), &vanished),
+            ],
+            ..Default::default()
+        });
+
+        assert!(h.app.state.mounts.is_empty());
+        assert!(!h.mounted("sda1"));
+        assert!(State::load(&h.app.config.state_path)
+            .unwrap()
+            .mounts
+            .is_empty());
+    }
+
+    #[test]
+    fn start_remounts_where_it_was() {
+        let mut h = Harness::new("remount");
+        h.app.config.remount_on_start = true;
+        let path = h.mount_path("STICK");
+        // Plugged back in after a reboot, under another kernel name
+        h.block.borrow_mut().partitions.push(Device {
+            name: "sdc1".to_string(),
+            uuid: Some("1234-ABCD".to_string()),
+            ..Default::default()
+        });
+        // Same name as before, but a different drive
+        h.block.borrow_mut().partitions.push(Device {
+            name: "sdb1".to_string(),
+            uuid: Some("5678-EF01".to_string()),
+            ..Default::default()
+        });
+
+        h.start_with(&State {
+            mounts: vec![
+                owned("sdb1", Some("1234-ABCD"), &path),
+                owned("sdb1", Some("9999-0000"), &h.mount_path("sdb1")),
+            ],
+            ..Default::default()
+        });
+
+        let block = h.block.borrow();
+        let sdc1 = block.partitions.iter().find(|x| x.name == "sdc1").unwrap();
+        assert!(sdc1.mounted);
+        assert_eq!(sdc1.path, path);
+        assert_eq!(sdc1.options, vec!["ro", "noatime"]);
+        assert!(!block
+            .partitions
+            .iter()
+            .any(|x| x.name == "sdb1" && x.mounted));
+        drop(block);
+
+        assert!(path.is_dir());
+        assert!(h.app.state.created_dirs.contains(&path));
+        assert!(h.smb_conf().contains("read only = yes"), "{}", h.smb_conf());
+        // The one not plugged in waits for its drive
+        assert_eq!(h.app.state.mounts.len(), 2);
+    }
+
+    #[test]
+    fn hotplug_remounts_a_drive_from_the_state() {
+        let mut h = Harness::new("remount-hotplug");
+        h.app.config.remount_on_start = true;
+        let path = h.mount_path("STICK");
+        let mut hotplug = FakeHotplug::new(h.block.clone());
+
+        h.start_with(&State {
+            mounts: vec![owned("sdb1", Some("1234-ABCD"), &path)],
+            ..Default::default()
+        });
+        assert_eq!(h.app.state.mounts.len(), 1, "kept for when it is plugged");
+
+        h.block.borrow_mut().partitions.push(Device {
+            name: "sdd1".to_string(),
+            uuid: Some("1234-ABCD".to_string()),
+            ..Default::default()
+        });
+        h.hotplug(&mut hotplug);
+
+        assert!(h.mounted("sdd1"));
+        assert_eq!(h.app.state.owned_paths(), vec![path]);
+    }
+
+    #[test]
+    fn hotplug_keeps_the_selected_device() {
+        let mut h = Harness::new("hotplug-keep");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.block.borrow_mut().plug("sdb1", "16G");
+        let mut hotplug = FakeHotplug::new(h.block.clone());
+
+        // The devices screen, then down to sdb1
+        h.press(&[Button::TopRight, Button::BottomLeft]);
+        h.block.borrow_mut().unplug("sda1");
+        h.block.borrow_mut().plug("sdc1", "32G");
+        h.hotplug(&mut hotplug);
+
+        assert_eq!(
+            h.device_list(),
+            (
+                vec!["sdb1".to_string(), "sdc1".to_string()],
+                "sdb1".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn hotplug_keeps_the_position_when_the_selection_goes() {
+        let mut h = Harness::new("hotplug-gone");
+        for name in ["sda1", "sdb1", "sdc1"] {
+            h.block.borrow_mut().plug(name, "8G");
+        }
+        let mut hotplug = FakeHotplug::new(h.block.clone());
+
+        h.press(&[Button::TopRight, Button::BottomLeft, Button::BottomLeft]);
+        h.block.borrow_mut().unplug("sdc1");
+        h.hotplug(&mut hotplug);
+
+        assert_eq!(
+            h.device_list(),
+            (
+                vec!["sda1".to_string(), "sdb1".to_string()],
+                "sdb1".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn unplugging_the_open_device_goes_back_to_the_list() {
+        let mut h = Harness::new("hotplug-detail");
+        h.block.borrow_mut().plug("sda1", "8G");
+        h.block.borrow_mut().plug("sdb1", "8G");
+        let mut hotplug = FakeHotplug::new(h.block.clone());
+
+        h.press(&[Button::TopRight, Button::BottomLeft, Button::TopLeft]);
+        assert_eq!(h.app.screen_name(), "device_detail");
+
+        h.block.borrow_mut().unplug("sdb1");
+        h.hotplug(&mut hotplug);
+
+        assert_eq!(
+            h.device_list(),
+            (vec!["sda1".to_string()], "sda1".to_string())
+        );
+    }
+
+    #[test]
+    fn mounting_and_ejecting_update_the_shares() {
+        let mut h = Harness::new("smb-update");
+        h.block.borrow_mut().plug("sda1", "8G");
+
+        h.mount_first();
+        assert!(h.smb_conf().contains("[sda1]"));
+        assert!(h
+            .smb_conf()
+            .contains(&format!("path = {}", h.mount_path("sda1").display())));
+        assert_eq!(h.smb.borrow().reloads, 1);
+        assert_eq!(h.app.state.shares.len(), 1);
+
+        h.press(&[Button::TopLeft]);
+        assert!(!h.smb_conf().contains("[sda1]"));
+        assert_eq!(h.smb.borrow().reloads, 2);
+        assert!(h.app.state.shares.is_empty());
+    }
+
+    #[test]
+    fn smb_screen_sets_the_password() {
+        let mut h = Harness::new("smb-password");
+        h.app.config.smb.username = "pi".to_string();
+        h.app.config.smb.password = "secret".to_string();
+
+        h.press(&[Button::BottomLeft, Button::TopRight]);
+
+        assert_eq!(h.app.screen_name(), "smb");
+        assert_eq!(
+            h.smb.borrow().passwords,
+            vec![("pi".to_string(), "secret".to_string())]
+        );
+    }
+
+    #[test]
+    fn failed_password_shows_an_error() {
+        let mut h = Harness::new("smb-password-fail");
+        h.smb.borrow_mut().fail = true;
+
+        h.press(&[Button::BottomLeft, Button::TopRight]);
+
+        assert_eq!(h.app.screen_name(), "error");
+    }
+
+    #[test]
+    fn unplugging_a_shared_device_drops_its_share() {
+        let mut h = Harness::new("smb-unplug");
+        h.block.borrow_mut().plug("sda1", "8G");
+        let mut hotplug = FakeHotplug::new(h.block.clone());
+
+        h.mount_first();
+        assert!(h.smb_conf().contains("[sda1]"));
+
+        h.block.borrow_mut().unplug("sda1");
+        h.hotplug(&mut hotplug);
+
+        assert!(!h.smb_conf().contains("[sda1]"));
+        assert!(h.app.state.shares.is_empty());
+    }
+
+    #[test]
+    fn read_only_toggle_updates_the_share() {
+        let mut h = Harness::new("smb-read-only");
+        h.block.borrow_mut().plug("sda1", "8G");
+
+        h.mount_first();
+        assert!(h.smb_conf().contains("read only = no"));
+
+        h.press(&[Button::TopRight]);
+        assert!(h.smb_conf().contains("read only = yes"));
+        assert!(h.app.state.shares[0].read_only);
+
+        h.press(&[Button::TopRight]);
+        assert!(h.smb_conf().contains("read only = no"));
+        assert!(!h.app.state.shares[0].read_only);
+    }
+
+    /// Draws `screen` on both display sizes and compares the ASCII art with
+    /// `src/snapshots/<name>.txt`
+    ///
+    /// A missing or different snapshot fails the test, run with
+    /// `UPDATE_SNAPSHOTS=1` to write it instead.
+    fn snapshot(name: &str, screen: Screen) {
+        let actual = [Size::new(128, 64), Size::new(128, 32)]
+            .into_iter()
+            .map(|size| {
+                let mut frame = FrameBuffer::new(size);
+                screen.draw(&mut frame).unwrap();
+                frame.to_ascii()
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/snapshots")
+            .join(format!("{name}.txt"));
+
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, &actual).unwrap();
+            return;
+        }
+
+        let expected = fs::read_to_string(&path).unwrap_or_else(|ex| {
+            panic!(
+                "could not read {}: {ex}, run with UPDATE_SNAPSHOTS=1 to write it",
+                path.display()
+            )
+        });
+        assert!(
+            expected == actual,
+            "{name} does not match {}, got\n{actual}",
+            path.display()
+        );
+    }
+
+    fn devices() -> Vec<Device> {
+        vec![
+            Device {
+                name: "sda1".to_string(),
+                mounted: true,
+                owned: true,
+                size: "32G".to_string(),
+                path: PathBuf::from("/mnt/PHOTOS"),
+                label: Some("PHOTOS".to_string()),
+                fstype: Some("vfat".to_string()),
+                model: Some("Cruzer Blade".to_string()),
+                fsused: Some("12G".to_string()),
+                fsavail: Some("20G".to_string()),
+                fsuse: Some(37),
+                options: vec!["rw".to_string(), "noatime".to_string()],
+                ..Default::default()
+            },
+            Device {
+                name: "sdb1".to_string(),
+                size: "500G".to_string(),
+                fstype: Some("ext4".to_string()),
+                model: Some("Portable SSD".to_string()),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn networks() -> Vec<Network> {
+        vec![
+            Network {
+                ssid: "Home".to_string(),
+                signal: 82,
+                secured: true,
+            },
+            Network {
+                ssid: "Cafe".to_string(),
+                signal: 40,
+                secured: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn home_snapshot() {
+        snapshot("home", Screen::Home);
+    }
+
+    #[test]
+    fn devices_snapshots() {
+        snapshot("devices", Screen::Devices(devices(), 1));
+        snapshot("devices_empty", Screen::Devices(vec![], 0));
+    }
+
+    #[test]
+    fn device_detail_snapshots() {
+        snapshot(
+            "device_detail_mounted",
+            Screen::DeviceDetail(devices(), 0, false),
+        );
+        snapshot(
+            "device_detail_read_only",
+            Screen::DeviceDetail(devices(), 1, true),
+        );
+    }
+
+    #[test]
+    fn wifi_snapshots() {
+        snapshot(
+            "wifi_disconnected",
+            Screen::Wifi(WifiState::Disconnected, networks(), 0),
+        );
+        snapshot(
+            "wifi_connected",
+            Screen::Wifi(WifiState::Connected("Home".to_string()), networks(), 1),
+        );
+        snapshot(
+            "wifi_hotspot",
+            Screen::Wifi(WifiState::Hotspot("drive-pi".to_string()), vec![], 0),
+        );
+    }
+
+    #[test]
+    fn smb_snapshots() {
+        snapshot("smb", Screen::Smb(smb::shares(&devices()), 0));
+        snapshot("smb_empty", Screen::Smb(vec![], 0));
+    }
+
+    #[test]
+    fn ejected_snapshot() {
+        snapshot("ejected", Screen::Ejected("PHOTOS".to_string()));
+    }
+
+    #[test]
+    fn error_snapshot() {
+        snapshot(
+            "error",
+            Screen::Error("Could not mount\nsdb1".to_string(), Some(3)),
+        );
+    }
+
+    #[test]
+    fn logs_snapshots() {
+        let lines = [
+            "INFO Loaded config",
+            "INFO Mounted device=sda1",
+            "WARN Could not remount",
+            "ERROR Could not unmount",
+        ];
+        snapshot(
+            "logs",
+            Screen::Logs(lines.iter().map(|x| x.to_string()).collect(), 1),
+        );
+        snapshot("logs_empty", Screen::Logs(vec![], 0));
+    }
+
+    #[test]
+    fn confirm_exit_snapshot() {
+        snapshot("confirm_exit", Screen::ConfirmExit);
+    }
+
+    #[test]
+    fn shutting_down_snapshot() {
+        snapshot("shutting_down", Screen::ShuttingDown);
+    }
+}
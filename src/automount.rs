@@ -0,0 +1,110 @@
+use crate::{
+    config::{AutomountConfig, AutomountMode, DeviceMatch},
+    device::Device,
+};
+
+/// Decides whether a newly inserted partition should be mounted straight away
+pub fn should_mount(config: &AutomountConfig, device: &Device) -> bool {
+    if device.mounted || config.deny.iter().any(|x| matches(x, device)) {
+        return false;
+    }
+
+    match config.mode {
+        AutomountMode::Off => false,
+        AutomountMode::All => true,
+        AutomountMode::Labels => config.allow.iter().any(|x| matches(x, device)),
+    }
+}
+
+fn matches(rule: &DeviceMatch, device: &Device) -> bool {
+    // An empty rule would match everything, which is never what was meant
+    if rule.label.is_none() && rule.uuid.is_none() && rule.fstype.is_none() {
+        return false;
+    }
+
+    field_matches(&rule.label, &device.label)
+        && field_matches(&rule.uuid, &device.uuid)
+        && field_matches(&rule.fstype, &device.fstype)
+}
+
+fn field_matches(rule: &Option<String>, value: &Option<String>) -> bool {
+    match (rule, value) {
+        (None, _) => true,
+        (Some(rule), Some(value)) => rule.eq_ignore_ascii_case(value),
+        (Some(_), None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(label: Option<&str>, uuid: Option<&str>, fstype: Option<&str>) -> DeviceMatch {
+        DeviceMatch {
+            label: label.map(str::to_string),
+            uuid: uuid.map(str::to_string),
+            fstype: fstype.map(str::to_string),
+        }
+    }
+
+    fn device(label: Option<&str>, uuid: &str, mounted: bool) -> Device {
+        Device {
+            name: "sda1".to_string(),
+            label: label.map(str::to_string),
+            uuid: Some(uuid.to_string()),
+            fstype: Some("vfat".to_string()),
+            mounted,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn should_mount_table() {
+        use AutomountMode::*;
+
+        let stick = device(Some("STICK"), "1234-ABCD", false);
+        let photos = device(Some("Photos"), "5678-EF01", false);
+        let unlabelled = device(None, "9ABC-DEF0", false);
+        let empty = rule(None, None, None);
+
+        #[rustfmt::skip]
+        let cases = [
+            // mode, allow, deny, device, expected, why
+            (Off, vec![], vec![], &stick, false, "off mounts nothing"),
+            (Off, vec![rule(Some("STICK"), None, None)], vec![], &stick, false, "off ignores allow"),
+            (All, vec![], vec![], &stick, true, "all mounts anything"),
+            (All, vec![], vec![], &unlabelled, true, "all mounts unlabelled"),
+            (All, vec![], vec![rule(Some("stick"), None, None)], &stick, false, "deny wins over all"),
+            (All, vec![], vec![rule(Some("STICK"), None, None)], &photos, true, "deny only its match"),
+            (Labels, vec![], vec![], &stick, false, "labels needs an allow"),
+            (Labels, vec![rule(Some("STICK"), None, None)], vec![], &stick, true, "label allowed"),
+            (Labels, vec![rule(Some("STICK"), None, None)], vec![], &photos, false, "other label"),
+            (Labels, vec![rule(Some("STICK"), None, None)], vec![], &unlabelled, false, "no label"),
+            (Labels, vec![rule(None, Some("1234-abcd"), None)], vec![], &stick, true, "uuid allowed"),
+            (Labels, vec![rule(None, Some("1234-ABCD"), None)], vec![], &photos, false, "other uuid"),
+            (Labels, vec![rule(Some("STICK"), Some("5678-EF01"), None)], vec![], &stick, false, "every field must match"),
+            (Labels, vec![rule(None, None, Some("vfat"))], vec![], &unlabelled, true, "fstype allowed"),
+            (Labels, vec![rule(Some("STICK"), None, None)], vec![rule(None, Some("1234-ABCD"), None)], &stick, false, "deny wins over allow"),
+            (Labels, vec![empty.clone()], vec![], &stick, false, "empty allow never matches"),
+            (All, vec![], vec![empty.clone()], &stick, true, "empty deny never matches"),
+        ];
+
+        for (mode, allow, deny, device, expected, why) in cases {
+            let config = AutomountConfig { mode, allow, deny };
+            assert_eq!(should_mount(&config, device), expected, "{why}");
+        }
+    }
+
+    #[test]
+    fn already_mounted() {
+        let config = AutomountConfig {
+            mode: AutomountMode::All,
+            ..Default::default()
+        };
+
+        assert!(!should_mount(
+            &config,
+            &device(Some("STICK"), "1234-ABCD", true)
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+//! Without any display hardware, buttons are read from stdin and every frame
+//! is printed as ASCII art, or saved to the path given as the only argument
+
+use drive_pi::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+    use framebuffer::FrameBuffer;
+    use input::Event;
+    use std::io::BufRead;
+
+    let output = std::env::args_os().nth(1).map(std::path::PathBuf::from);
+
+    let logs = logging::LogBuffer::default();
+    let (config, _log) = startup(&logs, false);
+    let (mut app, mut hotplug) = build_app(config, logs);
+
+    let mut display = FrameBuffer::for_config(&app.config().hardware);
+
+    let mut show = |app: &app::App| -> std::io::Result<()> {
+        display.clear(BinaryColor::Off).unwrap();
+        app.draw(&mut display).unwrap();
+
+        match &output {
+            Some(path) => display.save(path),
+            None => {
+                print!("{}", display.to_ascii());
+                Ok(())
+            }
+        }
+    };
+
+    show(&app)?;
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+
+        let event = match app.config().input.button_for_key(line.trim()) {
+            Some(button) => Event::Press(button),
+            None if line.trim() == "q" => Event::Shutdown,
+            None => continue,
+        };
+
+        let mut events = vec![Event::Tick(std::time::Instant::now())];
+        if let Some(Ok(val)) = hotplug.as_mut().map(|x| x.poll()) {
+            events.extend(val.into_iter().map(Event::Hotplug));
+        }
+        events.push(event);
+
+        let mut redraw = false;
+        for event in events {
+            redraw |= app.handle_event(event);
+        }
+
+        if app.should_exit() {
+            break;
+        }
+
+        if redraw {
+            show(&app)?;
+        }
+    }
+
+    Ok(())
+}
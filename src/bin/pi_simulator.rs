@@ -0,0 +1,85 @@
+//! The simulator standing in for the OLED and buttons under the Pi event
+//! loop, which runs on its own thread as the window needs the main one
+
+use drive_pi::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use framebuffer::FrameBuffer;
+    use piston_window::{PistonWindow, TextEvent, Window, WindowSettings};
+    use simulator::{ScreenSimulator, SharedFrame, SimulatorOptions};
+    use std::{
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    let logs = logging::LogBuffer::default();
+    let (config, _log) = startup(&logs, false);
+
+    let frame = FrameBuffer::for_config(&config.hardware);
+    let shared = Arc::new(Mutex::new(frame.clone()));
+    let mut display = ScreenSimulator::new(
+        frame.clone(),
+        SimulatorOptions::from_args(std::env::args().skip(1)),
+    );
+
+    let (edges, buttons) = futures::channel::mpsc::unbounded();
+
+    let worker = {
+        let shared = shared.clone();
+        let config = config.clone();
+
+        std::thread::spawn(move || -> Result<(), String> {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|ex| ex.to_string())?;
+
+            let (app, hotplug) = build_app(config, logs);
+
+            let mut display = SharedFrame { frame, shared };
+            runtime
+                .block_on(event_loop::run(app, &mut display, buttons, hotplug))
+                .map_err(|ex| ex.to_string())
+        })
+    };
+
+    let mut window: PistonWindow = WindowSettings::new("drive-pi", display.window_size())
+        .exit_on_esc(true)
+        .resizable(false)
+        .build()?;
+
+    let start = Instant::now();
+
+    while let Some(e) = window.next() {
+        if worker.is_finished() {
+            break;
+        }
+
+        let now = start.elapsed().as_nanos() as u64;
+
+        for (button, pressed) in display.handle(&e) {
+            let _ = edges.unbounded_send((button, pressed, now));
+        }
+
+        if let Some(text) = e.text_args() {
+            if text == "q" {
+                window.set_should_close(true);
+            } else if let Some(button) = config.input.button_for_key(&text) {
+                let _ = edges.unbounded_send((button, true, now));
+                let _ = edges.unbounded_send((button, false, now));
+            }
+        }
+
+        if let Ok(frame) = shared.lock() {
+            display.show(&frame);
+        }
+
+        window.draw_2d(&e, |c, g, _| display.draw(c, g));
+    }
+
+    // Ending the button stream stops the event loop
+    drop(edges);
+    worker.join().map_err(|_| "event loop panicked")??;
+
+    Ok(())
+}
@@ -0,0 +1,21 @@
+//! Runs a test script against the fake backends, see [`replay`]
+
+use drive_pi::*;
+
+fn main() {
+    let (update, args): (Vec<_>, Vec<_>) =
+        std::env::args_os().skip(1).partition(|x| x == "--update");
+
+    let path = match args.into_iter().next() {
+        Some(val) => std::path::PathBuf::from(val),
+        None => {
+            eprintln!("Usage: replay [--update] <script>");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(ex) = replay::run(&path, !update.is_empty()) {
+        eprintln!("{}: {ex}", path.display());
+        std::process::exit(1);
+    }
+}
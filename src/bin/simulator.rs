@@ -0,0 +1,83 @@
+//! The simulator on its own, timers and hotplug are driven from the window's
+//! update events. Pass `--zoom <n>`, `--grid` or `--glow` to change the look.
+
+use drive_pi::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+    use framebuffer::FrameBuffer;
+    use gesture::GestureRecogniser;
+    use input::Event;
+    use piston_window::{PistonWindow, TextEvent, UpdateEvent, WindowSettings};
+    use simulator::{ScreenSimulator, SimulatorOptions};
+    use std::time::{Duration, Instant};
+
+    let logs = logging::LogBuffer::default();
+    let (config, _log) = startup(&logs, false);
+    let (mut app, mut hotplug) = build_app(config, logs);
+
+    let mut display = ScreenSimulator::new(
+        FrameBuffer::for_config(&app.config().hardware),
+        SimulatorOptions::from_args(std::env::args().skip(1)),
+    );
+
+    let mut window: PistonWindow = WindowSettings::new("drive-pi", display.window_size())
+        .exit_on_esc(true)
+        .resizable(false)
+        .build()?;
+
+    let mut gestures = GestureRecogniser::new(app.config().gestures.clone());
+
+    let start = Instant::now();
+    let mut last_tick = start;
+    let mut redraw = true;
+
+    while let Some(e) = window.next() {
+        // Gesture times are nanoseconds, like the GPIO event timestamps
+        let now = start.elapsed().as_nanos() as u64;
+        let mut events = vec![];
+
+        for (button, pressed) in display.handle(&e) {
+            events.extend(gestures.edge(button, pressed, now));
+        }
+
+        if let Some(text) = e.text_args() {
+            match app.config().input.button_for_key(&text) {
+                Some(button) => events.push(Event::Press(button)),
+                None if text == "q" => events.push(Event::Shutdown),
+                None => {}
+            }
+        }
+
+        if e.update_args().is_some() {
+            events.extend(gestures.poll(now));
+
+            if last_tick.elapsed() >= Duration::from_secs(1) {
+                last_tick = Instant::now();
+                events.push(Event::Tick(last_tick));
+
+                if let Some(Ok(val)) = hotplug.as_mut().map(|x| x.poll()) {
+                    events.extend(val.into_iter().map(Event::Hotplug));
+                }
+            }
+        }
+
+        for event in events {
+            redraw |= app.handle_event(event);
+        }
+
+        if app.should_exit() {
+            break;
+        }
+
+        if redraw {
+            display.clear(BinaryColor::Off).unwrap();
+            app.draw(&mut display).unwrap();
+            redraw = false;
+        }
+
+        window.draw_2d(&e, |c, g, _| display.draw(c, g));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,13 @@
+//! Draws the display in the terminal, pass `--fake` to run against the in
+//! memory backends and `--braille` for a smaller picture
+
+use drive_pi::*;
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    tui::run(tui::Options {
+        fake: args.iter().any(|x| x == "--fake"),
+        braille: args.iter().any(|x| x == "--braille"),
+    })
+}
@@ -1,4 +1,13 @@
-use std::{fs::File, io::Read, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf};
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, Read},
+};
+
+use crate::input::Button;
+use crate::input::ButtonMap;
 
 macro_rules! s_default {
     {$name:ident $type:ty = $value:expr} => {
@@ -9,40 +18,511 @@ macro_rules! s_default {
 }
 
 s_default! { mount_path PathBuf = PathBuf::from("./") }
+s_default! { mount_name String = "kernel".to_string() }
+s_default! { state_path PathBuf = PathBuf::from("/var/lib/drive-pi/state.json") }
+s_default! { wifi_interface String = "wlan0".to_string() }
+s_default! { long_press_ms u64 = 800 }
+s_default! { repeat_ms u64 = 200 }
+s_default! { chord_ms u64 = 150 }
+s_default! { debounce_ms u64 = 20 }
+s_default! { input_keys ButtonMap<Vec<String>> = ButtonMap {
+    top_left: vec!["a".to_string(), "7".to_string()],
+    top_right: vec!["c".to_string(), "9".to_string()],
+    bottom_left: vec!["b".to_string(), "1".to_string()],
+    bottom_right: vec!["d".to_string(), "3".to_string()],
+} }
+s_default! { log_level String = "info".to_string() }
+s_default! { log_path PathBuf = PathBuf::from("/var/log/drive-pi/drive-pi.log") }
+s_default! { log_keep usize = 7 }
+s_default! { gpio_chip PathBuf = PathBuf::from("/dev/gpiochip0") }
+s_default! { i2c_bus PathBuf = PathBuf::from("/dev/i2c-1") }
+s_default! { i2c_address u16 = 0x3C }
+s_default! { button_lines ButtonMap<u32> = ButtonMap {
+    top_left: 4,
+    top_right: 15,
+    bottom_left: 14,
+    bottom_right: 18,
+} }
+s_default! { smb_conf_path PathBuf = PathBuf::from("/etc/samba/drive-pi.conf") }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(default = "mount_path")]
     pub mount_path: PathBuf,
+    /// `kernel`, `label`, `uuid` or a template such as `{label}-{uuid8}`
+    #[serde(default = "mount_name")]
+    pub mount_name: String,
+    /// Where drive-pi remembers what it changed, see [`crate::state::State`]
+    #[serde(default = "state_path")]
+    pub state_path: PathBuf,
+    #[serde(default)]
+    pub wifi: WifiConfig,
+    #[serde(default)]
+    pub smb: SmbConfig,
+    #[serde(default)]
+    pub automount: AutomountConfig,
+    /// Power down the disk after ejecting one of its partitions
+    #[serde(default)]
+    pub power_off_on_eject: bool,
+    /// Mount the drives drive-pi had mounted when it last stopped, such as
+    /// before a reboot, once they show up
+    #[serde(default)]
+    pub remount_on_start: bool,
+    #[serde(default)]
+    pub mount: MountConfig,
+    #[serde(default)]
+    pub input: InputConfig,
+    #[serde(default)]
+    pub gestures: GestureConfig,
+    #[serde(default)]
+    pub debounce: DebounceConfig,
+    #[serde(default)]
+    pub hardware: HardwareConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             mount_path: mount_path(),
+            mount_name: mount_name(),
+            state_path: state_path(),
+            wifi: WifiConfig::default(),
+            smb: SmbConfig::default(),
+            automount: AutomountConfig::default(),
+            power_off_on_eject: false,
+            remount_on_start: false,
+            mount: MountConfig::default(),
+            input: InputConfig::default(),
+            gestures: GestureConfig::default(),
+            debounce: DebounceConfig::default(),
+            hardware: HardwareConfig::default(),
+            logging: LoggingConfig::default(),
         }
     }
 }
 
-impl Config {
-    pub fn load() -> Option<Self> {
-        let mut file = match File::open("/etc/drive-pi/config.toml") {
-            Ok(file) => file,
-            Err(_) => match File::open("./config.toml") {
-                Ok(file) => file,
-                Err(ex) => {
-                    eprintln!("{ex:?}");
-                    return None;
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    /// Only keep the recent entries for the log screen
+    None,
+    #[default]
+    Stderr,
+    Journald,
+    /// Rotated files next to `path`
+    File,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// Where logs go and how much detail they have, `targets` overrides the
+/// level per module, like `"drive_pi::device" = "debug"`
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct LoggingConfig {
+    #[serde(default = "log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub targets: HashMap<String, String>,
+    #[serde(default)]
+    pub output: LogOutput,
+    #[serde(default = "log_path")]
+    pub path: PathBuf,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// How many rotated files to keep
+    #[serde(default = "log_keep")]
+    pub keep: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: log_level(),
+            targets: HashMap::new(),
+            output: LogOutput::default(),
+            path: log_path(),
+            rotation: LogRotation::default(),
+            keep: log_keep(),
+        }
+    }
+}
+
+/// Hotspot settings, the keys match the DietPi style `SSID`/`PASS` names
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct WifiConfig {
+    #[serde(rename = "SSID", default)]
+    pub ssid: String,
+    #[serde(rename = "PASS", default)]
+    pub pass: String,
+    #[serde(default = "wifi_interface")]
+    pub interface: String,
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self {
+        Self {
+            ssid: String::new(),
+            pass: String::new(),
+            interface: wifi_interface(),
+        }
+    }
+}
+
+/// Samba settings, `conf_path` is the fragment drive-pi owns and should be
+/// pulled into `smb.conf` with an `include =` line
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SmbConfig {
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "smb_conf_path")]
+    pub conf_path: PathBuf,
+}
+
+impl Default for SmbConfig {
+    fn default() -> Self {
+        Self {
+            username: String::new(),
+            password: String::new(),
+            conf_path: smb_conf_path(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutomountMode {
+    /// Only mount from the devices screen
+    #[default]
+    Off,
+    /// Mount everything that is not denied
+    All,
+    /// Mount only what is allowed and not denied
+    Labels,
+}
+
+/// Matches a partition when every field that is set matches
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct DeviceMatch {
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub fstype: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct AutomountConfig {
+    #[serde(default)]
+    pub mode: AutomountMode,
+    #[serde(default)]
+    pub allow: Vec<DeviceMatch>,
+    #[serde(default)]
+    pub deny: Vec<DeviceMatch>,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NtfsDriver {
+    /// The in kernel driver, Linux 5.15 and newer
+    #[default]
+    #[serde(rename = "ntfs3")]
+    Ntfs3,
+    /// The FUSE driver
+    #[serde(rename = "ntfs-3g")]
+    Ntfs3g,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct MountConfig {
+    /// Options passed for every filesystem, such as `noatime`
+    #[serde(default)]
+    pub options: Vec<String>,
+    /// Extra options keyed by fstype, such as `uid=1000` for `vfat`
+    #[serde(default)]
+    pub fstypes: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub ntfs_driver: NtfsDriver,
+}
+
+/// Which keys map to which button, for the simulator and the terminal
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct InputConfig {
+    #[serde(default = "input_keys")]
+    pub keys: ButtonMap<Vec<String>>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self { keys: input_keys() }
+    }
+}
+
+impl InputConfig {
+    pub fn button_for_key(&self, key: &str) -> Option<Button> {
+        self.keys.find(|keys| keys.iter().any(|x| x == key))
+    }
+}
+
+/// Thresholds for turning GPIO edges into gestures, in milliseconds
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct GestureConfig {
+    /// How long a button has to be held to count as a long press
+    #[serde(default = "long_press_ms")]
+    pub long_press_ms: u64,
+    /// Interval between repeats while a long press is held
+    #[serde(default = "repeat_ms")]
+    pub repeat_ms: u64,
+    /// How close together two presses have to be to count as a chord
+    #[serde(default = "chord_ms")]
+    pub chord_ms: u64,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            long_press_ms: long_press_ms(),
+            repeat_ms: repeat_ms(),
+            chord_ms: chord_ms(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct DebounceConfig {
+    /// Debounce window for every button, in milliseconds
+    #[serde(default = "debounce_ms")]
+    pub window_ms: u64,
+    /// Per button windows for switches that bounce more than the rest
+    #[serde(default)]
+    pub buttons: HashMap<Button, u64>,
+}
+
+impl Default for DebounceConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: debounce_ms(),
+            buttons: HashMap::new(),
+        }
+    }
+}
+
+impl DebounceConfig {
+    pub fn window_ms(&self, button: Button) -> u64 {
+        self.buttons.get(&button).copied().unwrap_or(self.window_ms)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Bias {
+    /// Leave the line as the firmware configured it
+    #[default]
+    AsIs,
+    PullUp,
+    PullDown,
+    Disabled,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    #[serde(rename = "0")]
+    Rotate0,
+    #[serde(rename = "90")]
+    Rotate90,
+    #[serde(rename = "180")]
+    Rotate180,
+    #[serde(rename = "270")]
+    Rotate270,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Controller {
+    #[default]
+    Ssd1306,
+    Sh1106,
+    Ssd1309,
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DisplaySize {
+    #[default]
+    #[serde(rename = "128x64")]
+    Size128x64,
+    #[serde(rename = "128x32")]
+    Size128x32,
+}
+
+/// Board wiring, so a new board revision only needs a config change
+///
+/// Only the display size is needed off the Pi, the rest is left unparsed.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct HardwareConfig {
+    #[serde(default = "gpio_chip")]
+    pub gpio_chip: PathBuf,
+    /// GPIO line offset of each button
+    #[serde(default = "button_lines")]
+    pub lines: ButtonMap<u32>,
+    /// Buttons read low when pressed, such as when they short to ground
+    #[serde(default)]
+    pub active_low: bool,
+    #[serde(default)]
+    pub bias: Bias,
+    #[serde(default = "i2c_bus")]
+    pub i2c_bus: PathBuf,
+    #[serde(default = "i2c_address")]
+    pub i2c_address: u16,
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default)]
+    pub controller: Controller,
+    #[serde(default)]
+    pub display_size: DisplaySize,
+}
+
+impl Default for HardwareConfig {
+    fn default() -> Self {
+        Self {
+            gpio_chip: gpio_chip(),
+            lines: button_lines(),
+            active_low: false,
+            bias: Bias::default(),
+            i2c_bus: i2c_bus(),
+            i2c_address: i2c_address(),
+            rotation: Rotation::default(),
+            controller: Controller::default(),
+            display_size: DisplaySize::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HardwareError {
+    SharedLine(u32, Button, Button),
+    I2cAddress(u16),
+    /// The bias pulls the line to the level that reads as pressed
+    BiasAgainstPolarity(Bias),
+    UnsupportedSize(Controller, DisplaySize),
+    /// The screens are laid out for a landscape display
+    Portrait(Rotation),
+}
+
+impl fmt::Display for HardwareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HardwareError::SharedLine(line, a, b) => {
+                write!(f, "GPIO line {line} is used by both {a:?} and {b:?}")
+            }
+            HardwareError::I2cAddress(address) => write!(
+                f,
+                "I2C address {address:#04x} is outside the 7 bit range 0x08 to 0x77"
+            ),
+            HardwareError::BiasAgainstPolarity(bias) => write!(
+                f,
+                "{bias:?} bias holds {} buttons pressed, set active_low to {}",
+                if *bias == Bias::PullUp {
+                    "active high"
+                } else {
+                    "active low"
+                },
+                *bias == Bias::PullUp,
+            ),
+            HardwareError::UnsupportedSize(controller, size) => {
+                write!(f, "{controller:?} displays do not come in {size:?}")
+            }
+            HardwareError::Portrait(rotation) => write!(
+                f,
+                "{rotation:?} turns the display portrait, only 0 and 180 are supported"
+            ),
+        }
+    }
+}
+
+impl Error for HardwareError {}
+
+impl HardwareConfig {
+    pub fn validate(&self) -> Result<(), HardwareError> {
+        for (i, a) in Button::ALL.iter().enumerate() {
+            for b in Button::ALL.iter().skip(i + 1) {
+                if self.lines.get(*a) == self.lines.get(*b) {
+                    return Err(HardwareError::SharedLine(*self.lines.get(*a), *a, *b));
                 }
-            },
-        };
+            }
+        }
+
+        if !(0x08..=0x77).contains(&self.i2c_address) {
+            return Err(HardwareError::I2cAddress(self.i2c_address));
+        }
+
+        if let (Controller::Ssd1309, DisplaySize::Size128x32) = (self.controller, self.display_size)
+        {
+            return Err(HardwareError::UnsupportedSize(
+                self.controller,
+                self.display_size,
+            ));
+        }
+
+        if let Rotation::Rotate90 | Rotation::Rotate270 = self.rotation {
+            return Err(HardwareError::Portrait(self.rotation));
+        }
+
+        match (self.bias, self.active_low) {
+            (Bias::PullUp, false) | (Bias::PullDown, true) => {
+                Err(HardwareError::BiasAgainstPolarity(self.bias))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NotFound,
+    Read(PathBuf, io::Error),
+    Invalid(PathBuf, toml::de::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NotFound => write!(f, "no config file found"),
+            ConfigError::Read(path, ex) => write!(f, "could not read {}: {ex}", path.display()),
+            ConfigError::Invalid(path, ex) => write!(f, "invalid config {}: {ex}", path.display()),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// Loads the first config file found, returning where it came from
+    ///
+    /// This runs before logging is set up, so failures are left to the caller
+    /// to report once it is.
+    pub fn load() -> Result<(Self, PathBuf), ConfigError> {
+        let (mut file, path) = ["/etc/drive-pi/config.toml", "./config.toml"]
+            .into_iter()
+            .map(PathBuf::from)
+            .find_map(|path| File::open(&path).ok().map(|file| (file, path)))
+            .ok_or(ConfigError::NotFound)?;
 
         let mut source = String::new();
         if let Err(ex) = file.read_to_string(&mut source) {
-            eprintln!("{ex:?}");
-            return None;
+            return Err(ConfigError::Read(path, ex));
         }
 
-        toml::from_str(&source).ok()
+        match toml::from_str(&source) {
+            Ok(config) => Ok((config, path)),
+            Err(ex) => Err(ConfigError::Invalid(path, ex)),
+        }
     }
 }
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use crate::{config::DebounceConfig, input::Button};
+
+const MS: u64 = 1_000_000;
+
+/// Raw and reported level of one button line
+struct Line {
+    /// Level after the most recent edge, bounces included
+    raw: bool,
+    raw_time: u64,
+    /// Level last let through
+    reported: bool,
+    reported_time: u64,
+}
+
+/// Drops the extra edges a bouncing switch produces
+///
+/// An edge that changes the button state is let through straight away if the
+/// last one let through is at least the debounce window old. Edges inside the
+/// window are held back, and once the line has been quiet for a whole window
+/// [`Debouncer::poll`] reports the level it settled on, so a release that
+/// comes quickly after a press is never lost. Times are GPIO event timestamps
+/// in nanoseconds.
+pub struct Debouncer {
+    config: DebounceConfig,
+    lines: HashMap<Button, Line>,
+}
+
+impl Debouncer {
+    pub fn new(config: DebounceConfig) -> Self {
+        Self {
+            config,
+            lines: HashMap::new(),
+        }
+    }
+
+    fn window(&self, button: Button) -> u64 {
+        self.config.window_ms(button) * MS
+    }
+
+    /// Records an edge, returning whether it should be acted on now
+    pub fn accept(&mut self, button: Button, pressed: bool, time: u64) -> bool {
+        let window = self.window(button);
+
+        let line = self.lines.entry(button).or_insert(Line {
+            raw: !pressed,
+            raw_time: 0,
+            reported: !pressed,
+            reported_time: 0,
+        });
+        line.raw = pressed;
+        line.raw_time = time;
+
+        if line.reported == pressed || time.saturating_sub(line.reported_time) < window {
+            return false;
+        }
+
+        line.reported = pressed;
+        line.reported_time = time;
+        true
+    }
+
+    /// Emits the levels that have settled by `now` and differ from what was
+    /// last reported, as `(button, pressed, time)` like the raw edges
+    pub fn poll(&mut self, now: u64) -> Vec<(Button, bool, u64)> {
+        let mut edges = vec![];
+
+        for (button, line) in self.lines.iter_mut() {
+            let window = self.config.window_ms(*button) * MS;
+
+            if line.raw != line.reported && now >= line.raw_time + window {
+                line.reported = line.raw;
+                line.reported_time = line.raw_time;
+                edges.push((*button, line.raw, line.raw_time));
+            }
+        }
+
+        edges
+    }
+
+    /// When [`Debouncer::poll`] next has something to emit
+    pub fn deadline(&self) -> Option<u64> {
+        self.lines
+            .iter()
+            .filter(|(_, line)| line.raw != line.reported)
+            .map(|(button, line)| line.raw_time + self.window(*button))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debouncer() -> Debouncer {
+        Debouncer::new(DebounceConfig {
+            window_ms: 20,
+            buttons: HashMap::from([(Button::BottomRight, 40)]),
+        })
+    }
+
+    #[test]
+    fn bounce_on_press() {
+        let mut debouncer = debouncer();
+
+        assert!(debouncer.accept(Button::TopLeft, true, 100 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, false, 101 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, true, 102 * MS));
+
+        assert_eq!(debouncer.deadline(), None);
+        assert!(debouncer.poll(200 * MS).is_empty());
+        assert!(debouncer.accept(Button::TopLeft, false, 300 * MS));
+    }
+
+    #[test]
+    fn bounce_on_release() {
+        let mut debouncer = debouncer();
+
+        assert!(debouncer.accept(Button::TopLeft, true, 100 * MS));
+        assert!(debouncer.accept(Button::TopLeft, false, 300 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, true, 301 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, false, 303 * MS));
+
+        assert_eq!(debouncer.deadline(), None);
+        assert!(debouncer.poll(400 * MS).is_empty());
+    }
+
+    #[test]
+    fn tap_shorter_than_window() {
+        let mut debouncer = debouncer();
+
+        assert!(debouncer.accept(Button::TopLeft, true, 100 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, false, 110 * MS));
+
+        assert_eq!(debouncer.deadline(), Some(130 * MS));
+        assert!(debouncer.poll(129 * MS).is_empty());
+        assert_eq!(
+            debouncer.poll(130 * MS),
+            vec![(Button::TopLeft, false, 110 * MS)]
+        );
+        assert_eq!(debouncer.deadline(), None);
+    }
+
+    #[test]
+    fn bouncy_tap_settles_released() {
+        let mut debouncer = debouncer();
+
+        assert!(debouncer.accept(Button::TopLeft, true, 100 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, false, 105 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, true, 106 * MS));
+        assert!(!debouncer.accept(Button::TopLeft, false, 108 * MS));
+
+        assert!(debouncer.poll(127 * MS).is_empty());
+        assert_eq!(
+            debouncer.poll(128 * MS),
+            vec![(Button::TopLeft, false, 108 * MS)]
+        );
+    }
+
+    #[test]
+    fn per_button_window() {
+        let mut debouncer = debouncer();
+
+        assert!(debouncer.accept(Button::BottomRight, true, 100 * MS));
+        assert!(!debouncer.accept(Button::BottomRight, false, 130 * MS));
+        assert!(debouncer.poll(150 * MS).is_empty());
+        assert_eq!(
+            debouncer.poll(170 * MS),
+            vec![(Button::BottomRight, false, 130 * MS)]
+        );
+
+        // The other buttons keep the default window
+        assert!(debouncer.accept(Button::TopRight, true, 100 * MS));
+        assert!(debouncer.accept(Button::TopRight, false, 130 * MS));
+    }
+}
@@ -1,11 +1,15 @@
+use crate::config::{MountConfig, NtfsDriver};
+use crate::state::State;
 use serde::{Deserialize, Serialize};
+use std::process::{Command, Output};
 use std::{
     error::Error,
-    fs::{self, remove_dir},
+    fmt, fs,
     io::{self, ErrorKind},
-    path::PathBuf,
-    process::Command,
+    path::{Path, PathBuf},
 };
+use tracing::debug;
+use tracing::{error, info};
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -16,6 +20,8 @@ pub struct DeviceQuery {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Blockdevice {
+    #[serde(default)]
+    pub model: Option<String>,
     #[serde(default)]
     pub children: Vec<Children>,
 }
@@ -26,63 +32,449 @@ pub struct Children {
     pub name: String,
     pub size: String,
     pub mountpoints: Vec<Option<String>>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    #[serde(default)]
+    pub fstype: Option<String>,
+    #[serde(default)]
+    pub fsused: Option<String>,
+    #[serde(default)]
+    pub fsavail: Option<String>,
+    #[serde(default, rename = "fsuse%")]
+    pub fsuse: Option<String>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default, Debug)]
 pub struct Device {
     pub name: String,
     pub mounted: bool,
+    /// Mounted by drive-pi, rather than by the system or by hand
+    pub owned: bool,
     pub size: String,
     pub path: PathBuf,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub fstype: Option<String>,
+    /// Model of the disk the partition is on
+    pub model: Option<String>,
+    pub fsused: Option<String>,
+    pub fsavail: Option<String>,
+    /// Percentage of the filesystem in use, only known while mounted
+    pub fsuse: Option<u8>,
+    /// Options the device is currently mounted with, empty when unmounted
+    pub options: Vec<String>,
 }
 
 impl Device {
-    pub fn mount(&self) -> io::Result<()> {
-        let dev = self.name.clone();
+    pub fn read_only(&self) -> bool {
+        self.options.iter().any(|x| x == "ro")
+    }
+
+    /// The filesystem label if there is one, otherwise the kernel name
+    pub fn display_name(&self) -> &str {
+        match &self.label {
+            Some(label) if !label.is_empty() => label,
+            _ => &self.name,
+        }
+    }
+}
+
+/// The `-t` and `-o` arguments for a mount
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MountOptions {
+    pub fstype: Option<String>,
+    pub options: Vec<String>,
+}
 
-        let target = self.path.clone();
+impl MountOptions {
+    pub fn new(config: &MountConfig, device: &Device, read_only: bool) -> Self {
+        let mut options = vec![if read_only { "ro" } else { "rw" }.to_string()];
+        options.extend(config.options.iter().cloned());
+
+        let fstype = device.fstype.as_deref().unwrap_or_default();
+        if let Some(extra) = config.fstypes.get(fstype) {
+            options.extend(extra.iter().cloned());
+        }
+
+        // Left alone mount would pick whichever ntfs driver it finds first
+        let fstype = match fstype {
+            "ntfs" => Some(
+                match config.ntfs_driver {
+                    NtfsDriver::Ntfs3 => "ntfs3",
+                    NtfsDriver::Ntfs3g => "ntfs-3g",
+                }
+                .to_string(),
+            ),
+            _ => None,
+        };
+
+        Self { fstype, options }
+    }
+}
+
+/// Why a block device operation failed
+///
+/// Most variants carry what the tool printed, for the logs.
+#[derive(Debug)]
+pub enum DeviceError {
+    /// A command drive-pi shells out to is not installed
+    MissingTool(&'static str),
+    /// `lsblk` printed something other than the expected JSON
+    Parse(String),
+    PermissionDenied(String),
+    /// The filesystem is not recognised or the kernel has no driver for it
+    UnknownFilesystem(String),
+    /// Something still has files open on the device
+    Busy(String),
+    AlreadyMounted(String),
+    /// Other partitions of the same disk are still mounted, named here
+    DiskInUse(String),
+    /// The device was unplugged while it was being used
+    Vanished(String),
+    /// The tool failed for any other reason
+    Failed(String),
+    Io(io::Error),
+}
+
+impl DeviceError {
+    /// Works out why mount(8) or umount(8) failed
+    ///
+    /// The exit codes only say that a mount failed, 32, or that it was used
+    /// wrongly or without permission, 1, so the message is checked first.
+    /// Only a missing `source` device counts as vanished, a missing mount
+    /// point is ours to fix.
+    fn from_mount(code: Option<i32>, stderr: &str, source: &Path) -> Self {
+        let msg = stderr.trim().to_string();
+        let lower = msg.to_lowercase();
+        let missing = lower.contains("does not exist")
+            && (lower.contains("special device") || msg.contains(&*source.to_string_lossy()));
+
+        if lower.contains("already mounted") {
+            DeviceError::AlreadyMounted(msg)
+        } else if lower.contains("target is busy") || lower.contains("device is busy") {
+            DeviceError::Busy(msg)
+        } else if lower.contains("unknown filesystem type")
+            || lower.contains("wrong fs type")
+            || lower.contains("can't read superblock")
+        {
+            DeviceError::UnknownFilesystem(msg)
+        } else if lower.contains("permission denied")
+            || lower.contains("operation not permitted")
+            || lower.contains("must be superuser")
+            || lower.contains("only root")
+        {
+            DeviceError::PermissionDenied(msg)
+        } else if missing
+            || lower.contains("no medium found")
+            || lower.contains("not a block device")
+        {
+            DeviceError::Vanished(msg)
+        } else {
+            match code {
+                Some(code) => DeviceError::Failed(format!("exited with {code}: {msg}")),
+                None => DeviceError::Failed(format!("killed: {msg}")),
+            }
+        }
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::MissingTool(tool) => write!(f, "{tool} is not installed"),
+            DeviceError::Parse(msg) => write!(f, "could not parse lsblk output: {msg}"),
+            DeviceError::PermissionDenied(msg) => write!(f, "permission denied: {msg}"),
+            DeviceError::UnknownFilesystem(msg) => write!(f, "unknown filesystem: {msg}"),
+            DeviceError::Busy(msg) => write!(f, "busy: {msg}"),
+            DeviceError::AlreadyMounted(msg) => write!(f, "already mounted: {msg}"),
+            DeviceError::DiskInUse(names) => write!(f, "disk still has {names} mounted"),
+            DeviceError::Vanished(msg) => write!(f, "device gone: {msg}"),
+            DeviceError::Failed(msg) => write!(f, "{msg}"),
+            DeviceError::Io(ex) => write!(f, "{ex}"),
+        }
+    }
+}
+
+impl Error for DeviceError {}
+
+impl From<io::Error> for DeviceError {
+    fn from(ex: io::Error) -> Self {
+        match ex.kind() {
+            ErrorKind::PermissionDenied => DeviceError::PermissionDenied(ex.to_string()),
+            _ => DeviceError::Io(ex),
+        }
+    }
+}
+
+/// Runs `command`, telling a missing `tool` apart from one that failed
+fn run(command: &mut Command, tool: &'static str) -> Result<Output, DeviceError> {
+    command.output().map_err(|ex| match ex.kind() {
+        ErrorKind::NotFound => DeviceError::MissingTool(tool),
+        _ => ex.into(),
+    })
+}
+
+/// Lists, mounts and unmounts block devices, implemented by [`Lsblk`] on the Pi
+pub trait BlockDeviceBackend {
+    /// Lists partitions, mounted ones carry the path they are mounted at and
+    /// are marked as owned if that is one of `owned`
+    fn devices(
+        &mut self,
+        mount_point: &Path,
+        owned: &[PathBuf],
+    ) -> Result<Vec<Device>, DeviceError>;
+    /// Mounts `device` at `device.path`, which has to exist already
+    fn mount(&mut self, device: &Device, options: &MountOptions) -> Result<(), DeviceError>;
+    /// Switches an already mounted device between read only and read write
+    fn remount(&mut self, device: &Device, read_only: bool) -> Result<(), DeviceError>;
+    /// Unmounts `device`, failing with [`DeviceError::Busy`] if something
+    /// still has files open on it
+    fn unmount(&mut self, device: &Device) -> Result<(), DeviceError>;
+    /// Flushes all pending writes to disk
+    fn sync(&mut self) -> Result<(), DeviceError>;
+    /// Names of the processes keeping `device` busy
+    fn holders(&mut self, device: &Device) -> Result<Vec<String>, DeviceError>;
+    /// Powers down the disk `device` is a partition of, failing with
+    /// [`DeviceError::DiskInUse`] while another of its partitions is mounted
+    fn power_off(&mut self, device: &Device) -> Result<(), DeviceError>;
+}
+
+/// Block device backend that shells out to `lsblk`, `mount` and `umount`
+pub struct Lsblk;
+
+impl BlockDeviceBackend for Lsblk {
+    fn devices(
+        &mut self,
+        mount_point: &Path,
+        owned: &[PathBuf],
+    ) -> Result<Vec<Device>, DeviceError> {
+        get_devices(mount_point, owned)
+    }
+
+    fn mount(&mut self, device: &Device, options: &MountOptions) -> Result<(), DeviceError> {
+        let dev = device.name.clone();
+
+        let target = device.path.clone();
         let source = PathBuf::from("/dev").join(dev);
 
-        if !target.exists() {
-            println!("Creating mount point");
+        if !source.exists() {
+            return Err(DeviceError::Vanished(format!(
+                "{} does not exist",
+                source.display()
+            )));
+        }
 
-            fs::create_dir(&target)?;
+        let mut command = Command::new("mount");
+        if let Some(fstype) = &options.fstype {
+            command.arg("-t").arg(fstype);
+        }
+        if !options.options.is_empty() {
+            command.arg("-o").arg(options.options.join(","));
         }
 
-        let output = Command::new("mount").arg(source).arg(&target).output()?;
-        let err_str = String::from_utf8(output.stderr).unwrap();
+        let output = run(command.arg(&source).arg(&target), "mount")?;
+        let err_str = String::from_utf8_lossy(&output.stderr);
 
-        println!("{err_str}");
-        println!("{}", output.status);
+        debug!(device = %device.name, status = %output.status, stderr = %err_str.trim(), "mount finished");
 
         if !output.status.success() {
-            return Err(io::Error::new(ErrorKind::Other, err_str));
+            return Err(DeviceError::from_mount(
+                output.status.code(),
+                &err_str,
+                &source,
+            ));
         }
 
         Ok(())
     }
 
-    pub fn unmount(&self) -> io::Result<()> {
-        let path = self.path.clone();
+    fn remount(&mut self, device: &Device, read_only: bool) -> Result<(), DeviceError> {
+        let mode = if read_only {
+            "remount,ro"
+        } else {
+            "remount,rw"
+        };
+        let output = run(
+            Command::new("mount").arg("-o").arg(mode).arg(&device.path),
+            "mount",
+        )?;
 
-        Command::new("umount").arg(&path).output()?;
-        remove_dir(&path)?;
+        if !output.status.success() {
+            return Err(DeviceError::from_mount(
+                output.status.code(),
+                &String::from_utf8_lossy(&output.stderr),
+                &Path::new("/dev").join(&device.name),
+            ));
+        }
 
         Ok(())
     }
+
+    fn unmount(&mut self, device: &Device) -> Result<(), DeviceError> {
+        let path = device.path.clone();
+
+        let output = run(Command::new("umount").arg(&path), "umount")?;
+
+        if !output.status.success() {
+            return Err(DeviceError::from_mount(
+                output.status.code(),
+                &String::from_utf8_lossy(&output.stderr),
+                &Path::new("/dev").join(&device.name),
+            ));
+        }
+
+        // umount can exit cleanly while a stacked mount is still in place
+        if is_mounted(&path)? {
+            return Err(DeviceError::Failed(format!(
+                "{} is still mounted",
+                path.display()
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), DeviceError> {
+        let status = run(&mut Command::new("sync"), "sync")?.status;
+
+        if !status.success() {
+            return Err(DeviceError::Failed(format!("sync failed with {status}")));
+        }
+
+        Ok(())
+    }
+
+    fn holders(&mut self, device: &Device) -> Result<Vec<String>, DeviceError> {
+        // fuser prints the pids on stdout and everything else on stderr
+        let output = run(Command::new("fuser").arg("-m").arg(&device.path), "fuser")?;
+        let pids = String::from_utf8_lossy(&output.stdout).into_owned();
+
+        let mut holders = vec![];
+        for pid in pids.split_whitespace() {
+            let pid: String = pid.chars().take_while(|x| x.is_ascii_digit()).collect();
+            let comm = fs::read_to_string(format!("/proc/{pid}/comm")).unwrap_or_default();
+
+            holders.push(format!("{} ({pid})", comm.trim()));
+        }
+
+        Ok(holders)
+    }
+
+    fn power_off(&mut self, device: &Device) -> Result<(), DeviceError> {
+        let disk = parent_disk(&device.name).map_err(|ex| DeviceError::Vanished(ex.to_string()))?;
+
+        // Cutting the power would pull the rest of the disk out from under
+        // whatever is using it
+        let mounted = mounted_partitions(&disk)?;
+        if !mounted.is_empty() {
+            return Err(DeviceError::DiskInUse(mounted.join(", ")));
+        }
+
+        match Command::new("udisksctl")
+            .arg("power-off")
+            .arg("-b")
+            .arg(PathBuf::from("/dev").join(&disk))
+            .output()
+        {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(DeviceError::Failed(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            )),
+            // Without udisks, detaching the SCSI device at least spins it down
+            Err(ex) if ex.kind() == ErrorKind::NotFound => Ok(fs::write(
+                PathBuf::from("/sys/block")
+                    .join(&disk)
+                    .join("device/delete"),
+                "1",
+            )?),
+            Err(ex) => Err(ex.into()),
+        }
+    }
+}
+
+fn is_mounted(path: &Path) -> io::Result<bool> {
+    Ok(mounted_options(path)?.is_some())
+}
+
+/// Options `path` is mounted with according to `/proc/mounts`
+fn mounted_options(path: &Path) -> io::Result<Option<Vec<String>>> {
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    let path = path.to_string_lossy().replace(' ', "\\040");
+
+    Ok(mounts
+        .lines()
+        .map(|x| x.split_whitespace().collect::<Vec<_>>())
+        .find(|x| x.len() > 3 && x[1] == path)
+        .map(|x| x[3].split(',').map(|x| x.to_string()).collect()))
 }
 
-pub fn get_devices(mount_point: &PathBuf) -> Result<Vec<Device>, Box<dyn Error>> {
-    let command = String::from_utf8(
-        std::process::Command::new("lsblk")
+/// `mount_point` as an absolute path without symlinks, the form mount points
+/// are listed in, so paths joined onto it compare equal to listed ones
+pub fn mount_root(mount_point: &Path) -> io::Result<PathBuf> {
+    // A fresh install has nowhere to mount to yet
+    if !mount_point.exists() {
+        debug!(path = %mount_point.display(), "Creating mount root");
+        fs::create_dir_all(mount_point)?;
+    }
+
+    mount_point.canonicalize()
+}
+
+/// Partitions of `disk` that are mounted anywhere, according to
+/// `/proc/mounts`
+fn mounted_partitions(disk: &str) -> io::Result<Vec<String>> {
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    let sources: Vec<&str> = mounts
+        .lines()
+        .filter_map(|x| x.split_whitespace().next())
+        .collect();
+
+    let mut names = vec![];
+    for entry in fs::read_dir(PathBuf::from("/sys/block").join(disk))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.starts_with(disk) && sources.contains(&format!("/dev/{name}").as_str()) {
+            names.push(name);
+        }
+    }
+    names.sort();
+
+    Ok(names)
+}
+
+/// Resolves a partition such as `sda1` to its disk, `sda`
+fn parent_disk(name: &str) -> io::Result<String> {
+    let path = fs::canonicalize(PathBuf::from("/sys/class/block").join(name))?;
+
+    path.parent()
+        .and_then(|x| x.file_name())
+        .map(|x| x.to_string_lossy().into_owned())
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("no parent for {name}")))
+}
+
+fn get_devices(mount_point: &Path, owned: &[PathBuf]) -> Result<Vec<Device>, DeviceError> {
+    let output = run(
+        Command::new("lsblk")
             .arg("--json")
-            .output()?
-            .stdout,
+            .arg("-o")
+            .arg("NAME,SIZE,MOUNTPOINTS,LABEL,UUID,FSTYPE,MODEL,FSUSED,FSAVAIL,FSUSE%"),
+        "lsblk",
     )?;
 
-    let output: DeviceQuery = serde_json::from_str(&command)?;
+    if !output.status.success() {
+        return Err(DeviceError::Failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    let command =
+        String::from_utf8(output.stdout).map_err(|ex| DeviceError::Parse(ex.to_string()))?;
+    let output: DeviceQuery =
+        serde_json::from_str(&command).map_err(|ex| DeviceError::Parse(ex.to_string()))?;
     let mut devices = vec![];
-    let path_str = format!("{}", mount_point.canonicalize().unwrap().display());
+
+    let mount_point = &mount_root(mount_point)?;
 
     for device in output.blockdevices.iter() {
         'inner: for part in device.children.iter() {
@@ -93,26 +485,399 @@ pub fn get_devices(mount_point: &PathBuf) -> Result<Vec<Device>, Box<dyn Error>>
                 .collect();
 
             let mut mounted = false;
+            let mut options = vec![];
+            let mut path = mount_point.join(&part.name);
 
-            if mountpoints.len() > 0 {
-                if mountpoints
-                    .iter()
-                    .find(|x| x.starts_with(path_str.as_str()))
-                    .is_none()
-                {
-                    continue 'inner;
-                }
+            if !mountpoints.is_empty() {
+                let ours = match mount_under(&mountpoints, mount_point) {
+                    Some(val) => val,
+                    None => continue 'inner,
+                };
                 mounted = true;
+                options = mounted_options(Path::new(ours))?.unwrap_or_default();
+                path = PathBuf::from(ours);
             }
 
             devices.push(Device {
                 name: part.name.clone(),
                 size: part.size.clone(),
                 mounted,
-                path: mount_point.join(part.name.clone()),
+                owned: mounted && owned.contains(&path),
+                path,
+                label: part.label.clone(),
+                uuid: part.uuid.clone(),
+                fstype: part.fstype.clone(),
+                model: device.model.clone(),
+                fsused: part.fsused.clone(),
+                fsavail: part.fsavail.clone(),
+                fsuse: part
+                    .fsuse
+                    .as_ref()
+                    .and_then(|x| x.trim_end_matches('%').parse().ok()),
+                options,
             });
         }
     }
 
     Ok(devices)
 }
+
+/// The first of `mountpoints` inside `root`, compared by path component so
+/// `/mnt/usb-old` is not inside `/mnt/usb`
+fn mount_under<'a>(mountpoints: &'a [String], root: &Path) -> Option<&'a String> {
+    mountpoints.iter().find(|x| Path::new(x).starts_with(root))
+}
+
+/// Unmounts the drives at `owned` under `mount_point`, for when the app
+/// cannot
+///
+/// Errors are only logged, this runs on the way out of a panic.
+pub fn unmount_all(mount_point: &Path, owned: &[PathBuf]) {
+    let devices = match get_devices(mount_point, owned) {
+        Ok(val) => val,
+        Err(ex) => {
+            error!(error = %ex, "Could not list devices to unmount");
+            return;
+        }
+    };
+
+    let mut lsblk = Lsblk;
+    if let Err(ex) = lsblk.sync() {
+        error!(error = %ex, "Could not sync");
+    }
+
+    for device in devices.iter().filter(|x| x.owned) {
+        match lsblk.unmount(device) {
+            Ok(_) => info!(device = %device.name, "Unmounted"),
+            Err(ex) => error!(device = %device.name, error = %ex, "Could not unmount"),
+        }
+    }
+}
+
+/// Makes a panic unmount the drives drive-pi mounted under `mount_point`
+/// before the process goes, as the app may not get to clean up itself
+///
+/// Which drives those are is read from the state file at `state_path` at the
+/// time, system mounts are left alone.
+pub fn unmount_on_panic(mount_point: PathBuf, state_path: PathBuf) {
+    let previous = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        error!(%info, "Panicked, unmounting everything");
+        match State::load(&state_path) {
+            Ok(state) => unmount_all(&mount_point, &state.owned_paths()),
+            Err(ex) => error!(error = %ex, "Could not read state, leaving drives mounted"),
+        }
+        previous(info);
+    }));
+}
+
+/// In memory block device backend, partitions can be plugged, unplugged and
+/// made to fail from the outside
+#[cfg(any(test, feature = "fake"))]
+#[derive(Default)]
+pub struct FakeBlockDevices {
+    pub partitions: Vec<Device>,
+    pub fail_list: bool,
+    pub fail_mount: Vec<String>,
+    pub fail_unmount: Vec<String>,
+    /// Partitions that refuse to unmount, with the processes holding them
+    pub busy: Vec<(String, Vec<String>)>,
+    pub syncs: usize,
+    pub powered_off: Vec<String>,
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl FakeBlockDevices {
+    pub fn plug(&mut self, name: &str, size: &str) {
+        self.partitions.push(Device {
+            name: name.to_string(),
+            size: size.to_string(),
+            ..Default::default()
+        });
+    }
+
+    pub fn unplug(&mut self, name: &str) {
+        self.partitions.retain(|x| x.name != name);
+    }
+
+    fn partition(&mut self, name: &str) -> Result<&mut Device, DeviceError> {
+        self.partitions
+            .iter_mut()
+            .find(|x| x.name == name)
+            .ok_or_else(|| DeviceError::Vanished(format!("no device {name}")))
+    }
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl BlockDeviceBackend for FakeBlockDevices {
+    fn devices(
+        &mut self,
+        mount_point: &Path,
+        owned: &[PathBuf],
+    ) -> Result<Vec<Device>, DeviceError> {
+        if self.fail_list {
+            return Err(DeviceError::Parse("fake lsblk failure".to_string()));
+        }
+
+        Ok(self
+            .partitions
+            .iter()
+            .map(|part| Device {
+                path: if part.mounted {
+                    part.path.clone()
+                } else {
+                    mount_point.join(&part.name)
+                },
+                owned: part.mounted && owned.contains(&part.path),
+                ..part.clone()
+            })
+            .collect())
+    }
+
+    fn mount(&mut self, device: &Device, options: &MountOptions) -> Result<(), DeviceError> {
+        if self.fail_mount.contains(&device.name) {
+            return Err(DeviceError::Failed(format!(
+                "fake mount failure {}",
+                device.name
+            )));
+        }
+
+        let part = self.partition(&device.name)?;
+        if part.mounted {
+            return Err(DeviceError::AlreadyMounted(format!(
+                "{} already mounted",
+                device.name
+            )));
+        }
+
+        part.mounted = true;
+        part.path = device.path.clone();
+        part.options = options.options.clone();
+        Ok(())
+    }
+
+    fn remount(&mut self, device: &Device, read_only: bool) -> Result<(), DeviceError> {
+        let part = self.partition(&device.name)?;
+        if !part.mounted {
+            return Err(DeviceError::Failed(format!("{} not mounted", device.name)));
+        }
+
+        part.options.retain(|x| x != "ro" && x != "rw");
+        part.options
+            .insert(0, if read_only { "ro" } else { "rw" }.to_string());
+        Ok(())
+    }
+
+    fn unmount(&mut self, device: &Device) -> Result<(), DeviceError> {
+        if self.fail_unmount.contains(&device.name) {
+            return Err(DeviceError::Failed(format!(
+                "fake unmount failure {}",
+                device.name
+            )));
+        }
+
+        if self.busy.iter().any(|(name, _)| *name == device.name) {
+            return Err(DeviceError::Busy(format!(
+                "{}: target is busy",
+                device.name
+            )));
+        }
+
+        let part = self.partition(&device.name)?;
+        part.mounted = false;
+        part.options.clear();
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), DeviceError> {
+        self.syncs += 1;
+        Ok(())
+    }
+
+    fn holders(&mut self, device: &Device) -> Result<Vec<String>, DeviceError> {
+        Ok(self
+            .busy
+            .iter()
+            .find(|(name, _)| *name == device.name)
+            .map(|(_, holders)| holders.clone())
+            .unwrap_or_default())
+    }
+
+    fn power_off(&mut self, device: &Device) -> Result<(), DeviceError> {
+        self.partition(&device.name)?;
+
+        // Partitions are named after their disk, like sdb1 and sdb2
+        let disk = device.name.trim_end_matches(|x: char| x.is_ascii_digit());
+        let mounted: Vec<&str> = self
+            .partitions
+            .iter()
+            .filter(|x| x.mounted && x.name.trim_end_matches(|x: char| x.is_ascii_digit()) == disk)
+            .map(|x| x.name.as_str())
+            .collect();
+        if !mounted.is_empty() {
+            return Err(DeviceError::DiskInUse(mounted.join(", ")));
+        }
+
+        self.powered_off.push(device.name.clone());
+        Ok(())
+    }
+}
+
+/// Lets a caller keep a handle on a fake backend after handing it to the app
+#[cfg(any(test, feature = "fake"))]
+impl<T: BlockDeviceBackend> BlockDeviceBackend for std::rc::Rc<std::cell::RefCell<T>> {
+    fn devices(
+        &mut self,
+        mount_point: &Path,
+        owned: &[PathBuf],
+    ) -> Result<Vec<Device>, DeviceError> {
+        self.borrow_mut().devices(mount_point, owned)
+    }
+
+    fn mount(&mut self, device: &Device, options: &MountOptions) -> Result<(), DeviceError> {
+        self.borrow_mut().mount(device, options)
+    }
+
+    fn remount(&mut self, device: &Device, read_only: bool) -> Result<(), DeviceError> {
+        self.borrow_mut().remount(device, read_only)
+    }
+
+    fn unmount(&mut self, device: &Device) -> Result<(), DeviceError> {
+        self.borrow_mut().unmount(device)
+    }
+
+    fn sync(&mut self) -> Result<(), DeviceError> {
+        self.borrow_mut().sync()
+    }
+
+    fn holders(&mut self, device: &Device) -> Result<Vec<String>, DeviceError> {
+        self.borrow_mut().holders(device)
+    }
+
+    fn power_off(&mut self, device: &Device) -> Result<(), DeviceError> {
+        self.borrow_mut().power_off(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_mount(stderr: &str) -> DeviceError {
+        DeviceError::from_mount(Some(32), stderr, Path::new("/dev/sda1"))
+    }
+
+    #[test]
+    fn missing_source_device_has_vanished() {
+        assert!(matches!(
+            from_mount("mount: /mnt/sda1-USB: special device /dev/sda1 does not exist."),
+            DeviceError::Vanished(_)
+        ));
+        assert!(matches!(
+            from_mount("mount: /dev/sda1: does not exist"),
+            DeviceError::Vanished(_)
+        ));
+    }
+
+    #[test]
+    fn missing_mount_point_is_a_failure() {
+        assert!(matches!(
+            from_mount("mount: /mnt/sda1-USB: mount point does not exist."),
+            DeviceError::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn mount_under_root() {
+        let mountpoints = |x: &[&str]| x.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        let root = Path::new("/mnt/usb");
+
+        assert_eq!(
+            mount_under(&mountpoints(&["/media/x", "/mnt/usb/sda1"]), root),
+            Some(&"/mnt/usb/sda1".to_string())
+        );
+        assert_eq!(
+            mount_under(&mountpoints(&["/mnt/usb-old/sda1"]), root),
+            None
+        );
+        assert_eq!(mount_under(&mountpoints(&["/mnt/usbsda1"]), root), None);
+        assert_eq!(mount_under(&mountpoints(&["/mnt"]), root), None);
+    }
+
+    #[test]
+    fn busy() {
+        for stderr in [
+            "umount: /mnt/sda1-USB: target is busy.",
+            "umount: /mnt/sda1-USB: device is busy.",
+        ] {
+            assert!(
+                matches!(from_mount(stderr), DeviceError::Busy(_)),
+                "{stderr}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_filesystem() {
+        for stderr in [
+            "mount: /mnt/sda1-USB: unknown filesystem type 'exfat'.",
+            "mount: /mnt/sda1-USB: wrong fs type, bad option, bad superblock on /dev/sda1, \
+             missing codepage or helper program, or other error.",
+            "mount: /mnt/sda1-USB: can't read superblock on /dev/sda1.",
+        ] {
+            assert!(
+                matches!(from_mount(stderr), DeviceError::UnknownFilesystem(_)),
+                "{stderr}"
+            );
+        }
+    }
+
+    #[test]
+    fn permission_denied() {
+        for stderr in [
+            "mount: /mnt/sda1-USB: must be superuser to use mount.",
+            "umount: /mnt/sda1-USB: must be superuser to unmount.",
+            "mount: only root can do that",
+            "mount: /mnt/sda1-USB: permission denied.",
+            "mount: /mnt/sda1-USB: operation not permitted.",
+        ] {
+            assert!(
+                matches!(from_mount(stderr), DeviceError::PermissionDenied(_)),
+                "{stderr}"
+            );
+        }
+    }
+
+    #[test]
+    fn already_mounted() {
+        for stderr in [
+            "mount: /mnt/sda1-USB: /dev/sda1 already mounted on /media/usb.",
+            "mount: /mnt/sda1-USB: /dev/sda1 already mounted or mount point busy.",
+        ] {
+            assert!(
+                matches!(from_mount(stderr), DeviceError::AlreadyMounted(_)),
+                "{stderr}"
+            );
+        }
+    }
+
+    #[test]
+    fn anything_else_keeps_the_exit_code() {
+        assert_eq!(
+            from_mount("mount: /mnt/sda1-USB: something new.\n").to_string(),
+            "exited with 32: mount: /mnt/sda1-USB: something new."
+        );
+        assert_eq!(
+            DeviceError::from_mount(None, "", Path::new("/dev/sda1")).to_string(),
+            "killed: "
+        );
+    }
+
+    #[test]
+    fn missing_tool() {
+        let result = run(&mut Command::new("drive-pi-no-such-tool"), "udisksctl");
+
+        assert!(matches!(result, Err(DeviceError::MissingTool("udisksctl"))));
+    }
+}
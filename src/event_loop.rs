@@ -0,0 +1,267 @@
+use std::{
+    error::Error,
+    io::BufRead,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use futures::stream::{Stream, StreamExt};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    app::App,
+    debounce::Debouncer,
+    gesture::{EventClock, GestureRecogniser},
+    hotplug::HotplugSource,
+    input::{Button, Event},
+};
+
+/// How many times a failed flush is tried again, re-initialising the display
+/// first, before the frame is dropped
+const FLUSH_RETRIES: u32 = 3;
+
+/// How long unmounting may take on the way out, after this anything still
+/// mounted is left and the process exits anyway
+const SHUTDOWN_TIME: Duration = Duration::from_secs(10);
+
+/// A display that buffers drawing until it is flushed
+pub trait Flush: DrawTarget<Color = BinaryColor> {
+    fn flush(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Sets the controller up again after it stopped responding, a glitch on
+    /// the I2C bus can leave it needing that
+    fn reinit(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// Draws the app and sends it to the display
+///
+/// A frame that cannot be shown is logged and skipped, the next redraw tries
+/// again, so a flaky display never stops the drives being managed.
+fn show<D>(app: &App, display: &mut D)
+where
+    D: Flush,
+    D::Error: std::fmt::Debug,
+{
+    if let Err(ex) = display.clear(BinaryColor::Off) {
+        error!(error = ?ex, "Could not clear display");
+        return;
+    }
+    if let Err(ex) = app.draw(display) {
+        error!(error = ?ex, "Could not draw");
+        return;
+    }
+
+    for attempt in 0..=FLUSH_RETRIES {
+        if attempt > 0 {
+            if let Err(ex) = display.reinit() {
+                warn!(attempt, error = %ex, "Could not re-initialise display");
+                continue;
+            }
+        }
+
+        match display.flush() {
+            Ok(_) => return,
+            Err(ex) => warn!(attempt, error = %ex, "Could not flush display"),
+        }
+    }
+
+    error!("Display is not responding, dropped a frame");
+}
+
+/// Unmounts everything and blanks the display, within [`SHUTDOWN_TIME`]
+///
+/// The unmounts are shell commands that can hang on a dying disk, so a
+/// watchdog ends the process if they overrun. It stands down once this
+/// returns.
+fn shutdown<D>(mut app: App, display: &mut D)
+where
+    D: Flush,
+    D::Error: std::fmt::Debug,
+{
+    let (_done, watchdog) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) =
+            watchdog.recv_timeout(SHUTDOWN_TIME + Duration::from_secs(2))
+        {
+            error!("Shutdown is taking too long, exiting anyway");
+            std::process::exit(1);
+        }
+    });
+
+    app.shutting_down();
+    show(&app, display);
+
+    app.shutdown(Instant::now() + SHUTDOWN_TIME);
+
+    // The app tidies its mount points as it drops
+    drop(app);
+
+    if let Err(ex) = display.clear(BinaryColor::Off) {
+        warn!(error = ?ex, "Could not blank display");
+        return;
+    }
+    if let Err(ex) = display.flush() {
+        warn!(error = %ex, "Could not blank display");
+    }
+}
+
+/// Reads lines from stdin on a thread of its own
+///
+/// Tokio's stdin blocks the runtime from shutting down until Enter is
+/// pressed, a detached thread is simply left behind on exit.
+fn stdin_lines() -> UnboundedReceiver<String> {
+    let (tx, rx) = unbounded_channel();
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    rx
+}
+
+/// Passes the button levels that have settled by `now` on to `gestures`
+fn settled(debouncer: &mut Debouncer, gestures: &mut GestureRecogniser, now: u64) -> Vec<Event> {
+    let mut events = vec![];
+
+    for (button, pressed, time) in debouncer.poll(now) {
+        debug!(?button, pressed, "Button settled");
+        events.extend(gestures.edge(button, pressed, time));
+    }
+
+    events
+}
+
+/// Runs the app until it exits, `buttons` ends or a signal asks it to stop,
+/// then shuts down
+///
+/// `buttons` yields raw edges, whether the button went down and a timestamp
+/// in nanoseconds, which are debounced and turned into gestures here.
+/// `hotplug` is polled once a second, without one the device list only
+/// updates when it is opened.
+pub async fn run<D, S>(
+    mut app: App,
+    display: &mut D,
+    mut buttons: S,
+    mut hotplug: Option<Box<dyn HotplugSource>>,
+) -> Result<(), Box<dyn Error>>
+where
+    D: Flush,
+    D::Error: std::fmt::Debug,
+    S: Stream<Item = (Button, bool, u64)> + Unpin,
+{
+    let mut debouncer = Debouncer::new(app.config().debounce.clone());
+    let mut gestures = GestureRecogniser::new(app.config().gestures.clone());
+    let mut clock = EventClock::default();
+
+    // Lets the buttons be pressed from a terminal, handy over SSH
+    let mut stdin = stdin_lines();
+
+    let mut hotplug_interval = tokio::time::interval(Duration::from_secs(1));
+
+    // systemctl stop, Ctrl-C and the terminal going away
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+
+    show(&app, display);
+
+    loop {
+        // Wake up when a button settles or is due a long press or repeat
+        let gesture_sleep = [debouncer.deadline(), gestures.deadline()]
+            .into_iter()
+            .flatten()
+            .min()
+            .zip(clock.now())
+            .map(|(deadline, now)| Duration::from_nanos(deadline.saturating_sub(now)));
+
+        let events = tokio::select! {
+            edge = buttons.next() => {
+                let (button, pressed, timestamp) = match edge {
+                    Some(val) => val,
+                    None => break,
+                };
+                clock.observe(timestamp);
+
+                // Levels that settled before this edge come first
+                let mut events = settled(&mut debouncer, &mut gestures, timestamp);
+
+                if debouncer.accept(button, pressed, timestamp) {
+                    debug!(?button, pressed, "Button");
+                    events.extend(gestures.edge(button, pressed, timestamp));
+                }
+
+                events
+            }
+            _ = tokio::time::sleep(gesture_sleep.unwrap_or_default()), if gesture_sleep.is_some() => {
+                match clock.now() {
+                    Some(now) => {
+                        let mut events = settled(&mut debouncer, &mut gestures, now);
+                        events.extend(gestures.poll(now));
+                        events
+                    }
+                    None => continue,
+                }
+            }
+            Some(line) = stdin.recv() => {
+                match app.config().input.button_for_key(line.trim()) {
+                    Some(button) => vec![Event::Press(button)],
+                    None if line.trim() == "q" => vec![Event::Shutdown],
+                    None => continue,
+                }
+            }
+            _ = hotplug_interval.tick() => {
+                let mut events = vec![Event::Tick(std::time::Instant::now())];
+
+                match hotplug.as_mut().map(|x| x.poll()) {
+                    Some(Ok(val)) => events.extend(val.into_iter().map(Event::Hotplug)),
+                    Some(Err(ex)) => warn!(error = %ex, "Could not poll for hotplug"),
+                    None => {}
+                };
+
+                events
+            }
+            Some(_) = sigterm.recv() => {
+                info!(signal = "SIGTERM", "Stopping");
+                vec![Event::Shutdown]
+            }
+            Some(_) = sigint.recv() => {
+                info!(signal = "SIGINT", "Stopping");
+                vec![Event::Shutdown]
+            }
+            Some(_) = sighup.recv() => {
+                info!(signal = "SIGHUP", "Stopping");
+                vec![Event::Shutdown]
+            }
+        };
+
+        let mut redraw = false;
+        for event in events {
+            redraw |= app.handle_event(event);
+        }
+
+        if app.should_exit() {
+            break;
+        }
+
+        if !redraw {
+            continue;
+        }
+
+        show(&app, display);
+    }
+
+    shutdown(app, display);
+
+    Ok(())
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use crate::config::{DisplaySize, HardwareConfig};
+
+/// In memory display with one bit per pixel, for rendering screens without
+/// any hardware attached
+///
+/// Frames can be dumped as PBM, PNG or ASCII art, which makes them easy to
+/// compare against golden snapshots.
+#[derive(Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    size: Size,
+    pixels: Vec<bool>,
+}
+
+impl FrameBuffer {
+    pub fn new(size: Size) -> Self {
+        Self {
+            size,
+            pixels: vec![false; (size.width * size.height) as usize],
+        }
+    }
+
+    /// A buffer the size of the display in the `[hardware]` config
+    pub fn for_config(config: &HardwareConfig) -> Self {
+        match config.display_size {
+            DisplaySize::Size128x64 => Self::new(Size::new(128, 64)),
+            DisplaySize::Size128x32 => Self::new(Size::new(128, 32)),
+        }
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let Size { width, height } = self.size;
+
+        if point.x < 0 || point.y < 0 || point.x >= width as i32 || point.y >= height as i32 {
+            return None;
+        }
+
+        Some(point.y as usize * width as usize + point.x as usize)
+    }
+
+    /// Whether the pixel at `point` is lit, out of range pixels are not
+    pub fn get(&self, point: Point) -> bool {
+        self.index(point).map(|x| self.pixels[x]).unwrap_or(false)
+    }
+
+    /// One line per row, `#` for lit pixels and `.` for dark ones
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::with_capacity(self.pixels.len() + self.size.height as usize);
+
+        for row in self.pixels.chunks(self.size.width as usize) {
+            out.extend(row.iter().map(|x| if *x { '#' } else { '.' }));
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Two pixel rows per line of text, using the Unicode half blocks
+    pub fn to_half_blocks(&self) -> Vec<String> {
+        let width = self.size.width as i32;
+
+        (0..self.size.height as i32)
+            .step_by(2)
+            .map(|y| {
+                (0..width)
+                    .map(
+                        |x| match (self.get(Point::new(x, y)), self.get(Point::new(x, y + 1))) {
+                            (false, false) => ' ',
+                            (true, false) => '▀',
+                            (false, true) => '▄',
+                            (true, true) => '█',
+                        },
+                    )
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// A 2x4 block of pixels per character, using the Unicode braille
+    /// patterns, which quarters the space the frame takes up in a terminal
+    pub fn to_braille(&self) -> Vec<String> {
+        // Dot numbering of the braille cells, by column then row
+        const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+        (0..self.size.height as i32)
+            .step_by(4)
+            .map(|y| {
+                (0..self.size.width as i32)
+                    .step_by(2)
+                    .map(|x| {
+                        let mut bits = 0;
+                        for (dx, column) in DOTS.iter().enumerate() {
+                            for (dy, dot) in column.iter().enumerate() {
+                                if self.get(Point::new(x + dx as i32, y + dy as i32)) {
+                                    bits |= dot;
+                                }
+                            }
+                        }
+
+                        char::from_u32(0x2800 + bits).unwrap_or(' ')
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Rows packed eight pixels to a byte, most significant bit first, with
+    /// the bit set when `lit` matches the pixel
+    fn packed(&self, lit: bool) -> Vec<u8> {
+        let mut out = vec![];
+
+        for row in self.pixels.chunks(self.size.width as usize) {
+            for byte in row.chunks(8) {
+                let mut packed = 0u8;
+                for (bit, pixel) in byte.iter().enumerate() {
+                    if *pixel == lit {
+                        packed |= 0x80 >> bit;
+                    }
+                }
+                out.push(packed);
+            }
+        }
+
+        out
+    }
+
+    /// Binary PBM (P4), lit pixels are white like on the OLED
+    pub fn write_pbm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "P4\n{} {}\n", self.size.width, self.size.height)?;
+        // PBM uses 1 for black
+        writer.write_all(&self.packed(false))
+    }
+
+    /// 1 bit greyscale PNG, lit pixels are white like on the OLED
+    pub fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer
+            .write_image_data(&self.packed(true))
+            .map_err(io::Error::other)
+    }
+
+    /// Saves the frame in the format picked by the extension of `path`,
+    /// `.png`, `.pbm` or anything else for ASCII art
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("png") => self.write_png(&mut writer)?,
+            Some("pbm") => self.write_pbm(&mut writer)?,
+            _ => writer.write_all(self.to_ascii().as_bytes())?,
+        }
+
+        writer.flush()
+    }
+}
+
+impl fmt::Debug for FrameBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_ascii())
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = BinaryColor;
+
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, colour) in pixels {
+            if let Some(index) = self.index(point) {
+                self.pixels[index] = colour.is_on();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self, colour: Self::Color) -> Result<(), Self::Error> {
+        self.pixels.fill(colour.is_on());
+
+        Ok(())
+    }
+}
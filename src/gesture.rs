@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::{
+    config::GestureConfig,
+    input::{Button, Event},
+};
+
+const MS: u64 = 1_000_000;
+
+struct Held {
+    since: u64,
+    /// Set once the hold has turned into a long press, after which releasing
+    /// the button no longer counts as a short press
+    next_repeat: Option<u64>,
+    chorded: bool,
+}
+
+/// Turns raw button edges into presses, long presses, repeats and chords
+///
+/// Times are in nanoseconds on the clock of the GPIO event timestamps.
+pub struct GestureRecogniser {
+    config: GestureConfig,
+    held: HashMap<Button, Held>,
+}
+
+impl GestureRecogniser {
+    pub fn new(config: GestureConfig) -> Self {
+        Self {
+            config,
+            held: HashMap::new(),
+        }
+    }
+
+    pub fn edge(&mut self, button: Button, pressed: bool, time: u64) -> Vec<Event> {
+        let mut events = self.poll(time);
+
+        if pressed {
+            if self.held.contains_key(&button) {
+                return events;
+            }
+
+            let mut chorded = false;
+            let chord_window = self.config.chord_ms * MS;
+            for (other, held) in self.held.iter_mut() {
+                if !held.chorded
+                    && held.next_repeat.is_none()
+                    && time.saturating_sub(held.since) <= chord_window
+                {
+                    held.chorded = true;
+                    chorded = true;
+                    events.push(Event::Chord(*other, button));
+                    break;
+                }
+            }
+
+            self.held.insert(
+                button,
+                Held {
+                    since: time,
+                    next_repeat: None,
+                    chorded,
+                },
+            );
+        } else if let Some(held) = self.held.remove(&button) {
+            if !held.chorded && held.next_repeat.is_none() {
+                events.push(Event::Press(button));
+            }
+        }
+
+        events
+    }
+
+    /// Emits the long presses and repeats that are due at `now`
+    pub fn poll(&mut self, now: u64) -> Vec<Event> {
+        let mut events = vec![];
+
+        for (button, held) in self.held.iter_mut() {
+            if held.chorded {
+                continue;
+            }
+
+            match held.next_repeat {
+                None if now >= held.since + self.config.long_press_ms * MS => {
+                    events.push(Event::LongPress(*button));
+                    held.next_repeat = Some(now + self.config.repeat_ms * MS);
+                }
+                Some(next) if now >= next => {
+                    events.push(Event::Repeat(*button));
+                    held.next_repeat = Some(now + self.config.repeat_ms * MS);
+                }
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    /// When [`GestureRecogniser::poll`] next has something to emit
+    pub fn deadline(&self) -> Option<u64> {
+        self.held
+            .values()
+            .filter(|x| !x.chorded)
+            .map(|x| {
+                x.next_repeat
+                    .unwrap_or(x.since + self.config.long_press_ms * MS)
+            })
+            .min()
+    }
+}
+
+/// Relates the GPIO timestamp clock to [`Instant`], so the recogniser can be
+/// polled between events
+#[derive(Default)]
+pub struct EventClock {
+    anchor: Option<(u64, Instant)>,
+}
+
+impl EventClock {
+    pub fn observe(&mut self, timestamp: u64) {
+        self.anchor = Some((timestamp, Instant::now()));
+    }
+
+    pub fn now(&self) -> Option<u64> {
+        self.anchor
+            .map(|(timestamp, at)| timestamp + at.elapsed().as_nanos() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recogniser() -> GestureRecogniser {
+        GestureRecogniser::new(GestureConfig {
+            long_press_ms: 800,
+            repeat_ms: 200,
+            chord_ms: 150,
+        })
+    }
+
+    #[test]
+    fn press_on_release() {
+        let mut gestures = recogniser();
+
+        assert!(gestures.edge(Button::TopLeft, true, 100 * MS).is_empty());
+        assert!(gestures.poll(500 * MS).is_empty());
+        assert_eq!(
+            gestures.edge(Button::TopLeft, false, 600 * MS),
+            vec![Event::Press(Button::TopLeft)]
+        );
+        assert_eq!(gestures.deadline(), None);
+    }
+
+    #[test]
+    fn long_press_at_threshold() {
+        let mut gestures = recogniser();
+
+        gestures.edge(Button::BottomRight, true, 100 * MS);
+
+        assert!(gestures.poll(899 * MS).is_empty());
+        assert_eq!(
+            gestures.poll(900 * MS),
+            vec![Event::LongPress(Button::BottomRight)]
+        );
+        // Already handled as a long press, so not a short one as well
+        assert!(gestures
+            .edge(Button::BottomRight, false, 950 * MS)
+            .is_empty());
+    }
+
+    #[test]
+    fn repeat_cadence() {
+        let mut gestures = recogniser();
+
+        gestures.edge(Button::BottomLeft, true, 100 * MS);
+        gestures.poll(900 * MS);
+
+        assert!(gestures.poll(1099 * MS).is_empty());
+        assert_eq!(
+            gestures.poll(1100 * MS),
+            vec![Event::Repeat(Button::BottomLeft)]
+        );
+        assert!(gestures.poll(1299 * MS).is_empty());
+        assert_eq!(
+            gestures.poll(1300 * MS),
+            vec![Event::Repeat(Button::BottomLeft)]
+        );
+        assert!(gestures
+            .edge(Button::BottomLeft, false, 1350 * MS)
+            .is_empty());
+    }
+
+    #[test]
+    fn chord_inside_window() {
+        let mut gestures = recogniser();
+
+        gestures.edge(Button::TopLeft, true, 100 * MS);
+
+        assert_eq!(
+            gestures.edge(Button::TopRight, true, 250 * MS),
+            vec![Event::Chord(Button::TopLeft, Button::TopRight)]
+        );
+        // Chorded buttons never long press or press on their own
+        assert!(gestures.poll(2000 * MS).is_empty());
+        assert!(gestures.edge(Button::TopLeft, false, 2100 * MS).is_empty());
+        assert!(gestures.edge(Button::TopRight, false, 2100 * MS).is_empty());
+    }
+
+    #[test]
+    fn no_chord_outside_window() {
+        let mut gestures = recogniser();
+
+        gestures.edge(Button::TopLeft, true, 100 * MS);
+
+        assert!(gestures.edge(Button::TopRight, true, 251 * MS).is_empty());
+        assert_eq!(
+            gestures.edge(Button::TopRight, false, 300 * MS),
+            vec![Event::Press(Button::TopRight)]
+        );
+        assert_eq!(
+            gestures.edge(Button::TopLeft, false, 350 * MS),
+            vec![Event::Press(Button::TopLeft)]
+        );
+    }
+
+    #[test]
+    fn releasing_one_button_of_a_chord() {
+        let mut gestures = recogniser();
+
+        gestures.edge(Button::TopLeft, true, 100 * MS);
+        gestures.edge(Button::TopRight, true, 150 * MS);
+
+        assert!(gestures.edge(Button::TopRight, false, 300 * MS).is_empty());
+        // The one still held stays part of the chord
+        assert!(gestures.poll(2000 * MS).is_empty());
+        assert!(gestures.edge(Button::TopLeft, false, 2100 * MS).is_empty());
+
+        // And the next press is a fresh one
+        gestures.edge(Button::TopRight, true, 2200 * MS);
+        assert_eq!(
+            gestures.edge(Button::TopRight, false, 2300 * MS),
+            vec![Event::Press(Button::TopRight)]
+        );
+    }
+
+    #[test]
+    fn deadline() {
+        let mut gestures = recogniser();
+        assert_eq!(gestures.deadline(), None);
+
+        gestures.edge(Button::TopLeft, true, 100 * MS);
+        assert_eq!(gestures.deadline(), Some(900 * MS));
+
+        gestures.edge(Button::BottomLeft, true, 400 * MS);
+        assert_eq!(gestures.deadline(), Some(900 * MS));
+
+        gestures.poll(900 * MS);
+        assert_eq!(gestures.deadline(), Some(1100 * MS));
+
+        gestures.edge(Button::TopLeft, false, 1000 * MS);
+        assert_eq!(gestures.deadline(), Some(1200 * MS));
+
+        // Chorded buttons have nothing coming
+        gestures.edge(Button::BottomLeft, false, 1000 * MS);
+        gestures.edge(Button::TopLeft, true, 1100 * MS);
+        gestures.edge(Button::TopRight, true, 1150 * MS);
+        assert_eq!(gestures.deadline(), None);
+    }
+}
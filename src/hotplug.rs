@@ -0,0 +1,181 @@
+#[cfg(any(test, feature = "fake"))]
+use std::{cell::RefCell, rc::Rc};
+use std::{collections::HashSet, io};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(any(test, feature = "fake"))]
+use crate::device::FakeBlockDevices;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotplugEvent {
+    Added(String),
+    Removed(String),
+}
+
+/// Source of partition add/remove events, polled from the main loop
+pub trait HotplugSource {
+    /// Returns every change seen since the previous call
+    fn poll(&mut self) -> io::Result<Vec<HotplugEvent>>;
+}
+
+/// Hotplug source that diffs the partitions listed under `/sys/block`
+pub struct SysBlock {
+    root: PathBuf,
+    known: HashSet<String>,
+}
+
+impl SysBlock {
+    pub fn new(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        let known = partitions(&root)?;
+
+        Ok(Self { root, known })
+    }
+}
+
+impl HotplugSource for SysBlock {
+    fn poll(&mut self) -> io::Result<Vec<HotplugEvent>> {
+        let current = partitions(&self.root)?;
+
+        Ok(changes(&mut self.known, current))
+    }
+}
+
+/// What changed between `known` and `current`, removals first and in name
+/// order, then remembers `current`
+fn changes(known: &mut HashSet<String>, current: HashSet<String>) -> Vec<HotplugEvent> {
+    let mut removed: Vec<_> = known.difference(&current).cloned().collect();
+    let mut added: Vec<_> = current.difference(known).cloned().collect();
+    removed.sort();
+    added.sort();
+
+    *known = current;
+
+    removed
+        .into_iter()
+        .map(HotplugEvent::Removed)
+        .chain(added.into_iter().map(HotplugEvent::Added))
+        .collect()
+}
+
+/// Partitions are the subdirectories of `/sys/block/<disk>` that carry a
+/// `partition` attribute, which matches the `children` lsblk reports
+fn partitions(root: &Path) -> io::Result<HashSet<String>> {
+    let mut found = HashSet::new();
+
+    for disk in fs::read_dir(root)?.filter_map(|x| x.ok()) {
+        let disk_name = disk.file_name().to_string_lossy().into_owned();
+        if disk_name.starts_with("loop") || disk_name.starts_with("ram") {
+            continue;
+        }
+
+        let entries = match fs::read_dir(disk.path()) {
+            Ok(val) => val,
+            Err(_) => continue,
+        };
+
+        for entry in entries.filter_map(|x| x.ok()) {
+            if entry.path().join("partition").exists() {
+                found.insert(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+/// Hotplug source that diffs the partitions of a fake block backend, the way
+/// [`SysBlock`] diffs `/sys/block`
+#[cfg(any(test, feature = "fake"))]
+pub struct FakeHotplug {
+    block: Rc<RefCell<FakeBlockDevices>>,
+    known: HashSet<String>,
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl FakeHotplug {
+    pub fn new(block: Rc<RefCell<FakeBlockDevices>>) -> Self {
+        let known = names(&block.borrow());
+
+        Self { block, known }
+    }
+}
+
+#[cfg(any(test, feature = "fake"))]
+fn names(block: &FakeBlockDevices) -> HashSet<String> {
+    block.partitions.iter().map(|x| x.name.clone()).collect()
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl HotplugSource for FakeHotplug {
+    fn poll(&mut self) -> io::Result<Vec<HotplugEvent>> {
+        let current = names(&self.block.borrow());
+
+        Ok(changes(&mut self.known, current))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sys_block_sees_partitions_come_and_go() {
+        let root = std::env::temp_dir().join(format!("drive-pi-sys-block-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        let partition = |disk: &str, name: &str| {
+            let dir = root.join(disk).join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("partition"), "1").unwrap();
+        };
+        partition("sda", "sda1");
+        // Loop devices and anything without a partition attribute are skipped
+        partition("loop0", "loop0p1");
+        fs::create_dir_all(root.join("sda/queue")).unwrap();
+
+        let mut hotplug = SysBlock::new(&root).unwrap();
+        assert_eq!(hotplug.poll().unwrap(), vec![]);
+
+        partition("sdb", "sdb1");
+        partition("sdb", "sdb2");
+        assert_eq!(
+            hotplug.poll().unwrap(),
+            vec![
+                HotplugEvent::Added("sdb1".to_string()),
+                HotplugEvent::Added("sdb2".to_string()),
+            ]
+        );
+
+        fs::remove_dir_all(root.join("sda")).unwrap();
+        assert_eq!(
+            hotplug.poll().unwrap(),
+            vec![HotplugEvent::Removed("sda1".to_string())]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn fake_follows_the_fake_block_devices() {
+        let block = Rc::new(RefCell::new(FakeBlockDevices::default()));
+        block.borrow_mut().plug("sda1", "8G");
+
+        let mut hotplug = FakeHotplug::new(block.clone());
+        assert_eq!(hotplug.poll().unwrap(), vec![]);
+
+        block.borrow_mut().plug("sdb1", "16G");
+        block.borrow_mut().unplug("sda1");
+        assert_eq!(
+            hotplug.poll().unwrap(),
+            vec![
+                HotplugEvent::Removed("sda1".to_string()),
+                HotplugEvent::Added("sdb1".to_string()),
+            ]
+        );
+        assert_eq!(hotplug.poll().unwrap(), vec![]);
+    }
+}
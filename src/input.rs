@@ -0,0 +1,85 @@
+use std::time::Instant;
+
+use crate::hotplug::HotplugEvent;
+
+/// The four buttons in the corners of the display
+///
+/// Parsed from a string so it also works as a TOML table key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub enum Button {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Button {
+    pub const ALL: [Button; 4] = [
+        Button::TopLeft,
+        Button::TopRight,
+        Button::BottomLeft,
+        Button::BottomRight,
+    ];
+}
+
+impl std::str::FromStr for Button {
+    type Err = String;
+
+    /// Parses the snake case names used in the config, like `top_left`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top_left" => Ok(Button::TopLeft),
+            "top_right" => Ok(Button::TopRight),
+            "bottom_left" => Ok(Button::BottomLeft),
+            "bottom_right" => Ok(Button::BottomRight),
+            _ => Err(format!("unknown button {s}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Button {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Press(Button),
+    LongPress(Button),
+    /// Sent periodically while a button stays held after a long press
+    Repeat(Button),
+    /// Two buttons pressed together, in the order they went down
+    Chord(Button, Button),
+    Hotplug(HotplugEvent),
+    Tick(Instant),
+    Shutdown,
+}
+
+/// One value per button, used for the key and GPIO line mappings
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+pub struct ButtonMap<T> {
+    pub top_left: T,
+    pub top_right: T,
+    pub bottom_left: T,
+    pub bottom_right: T,
+}
+
+impl<T> ButtonMap<T> {
+    pub fn get(&self, button: Button) -> &T {
+        match button {
+            Button::TopLeft => &self.top_left,
+            Button::TopRight => &self.top_right,
+            Button::BottomLeft => &self.bottom_left,
+            Button::BottomRight => &self.bottom_right,
+        }
+    }
+
+    /// The first button whose value matches `predicate`
+    pub fn find(&self, predicate: impl Fn(&T) -> bool) -> Option<Button> {
+        Button::ALL.into_iter().find(|x| predicate(self.get(*x)))
+    }
+}
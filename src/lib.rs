@@ -0,0 +1,84 @@
+pub mod app;
+pub mod automount;
+pub mod config;
+pub mod debounce;
+pub mod device;
+#[cfg(feature = "pi")]
+pub mod event_loop;
+pub mod framebuffer;
+pub mod gesture;
+pub mod hotplug;
+pub mod input;
+pub mod logging;
+pub mod naming;
+#[cfg(feature = "pi")]
+pub mod oled;
+#[cfg(any(test, feature = "replay"))]
+pub mod replay;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+pub mod smb;
+pub mod state;
+#[cfg(any(test, feature = "fake"))]
+pub mod tempdir;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod wifi;
+
+use app::App;
+use config::{Config, LogOutput};
+use hotplug::HotplugSource;
+use logging::LogBuffer;
+use tracing_appender::non_blocking::WorkerGuard;
+
+/// Loads the config and starts logging into `logs`, the way every entry
+/// point starts
+///
+/// `quiet` keeps the log off stderr, for when that is the terminal being
+/// drawn on. The guard keeps the log file open and has to live until exit.
+pub fn startup(logs: &LogBuffer, quiet: bool) -> (Config, Option<WorkerGuard>) {
+    let (config, loaded) = match Config::load() {
+        Ok((config, path)) => (config, Ok(path)),
+        Err(ex) => (Config::default(), Err(ex)),
+    };
+
+    let mut log_config = config.logging.clone();
+    if quiet && log_config.output == LogOutput::Stderr {
+        log_config.output = LogOutput::None;
+    }
+    let guard = logging::init(&log_config, logs.clone());
+    logging::config_loaded(&loaded);
+
+    (config, guard)
+}
+
+/// Picks up the state from the last run, once a panic is sure to unmount
+/// drive-pi's drives
+pub fn resume(app: &mut App) {
+    device::unmount_on_panic(
+        app.config().mount_path.clone(),
+        app.config().state_path.clone(),
+    );
+    app.load_state();
+}
+
+/// The app and where its hotplug events come from, which is the in memory
+/// backends when built with `fake` and run with `--fake`
+pub fn build_app(config: Config, logs: LogBuffer) -> (App, Option<Box<dyn HotplugSource>>) {
+    #[cfg(feature = "fake")]
+    if std::env::args().any(|x| x == "--fake") {
+        let (app, block) = App::demo(config, logs);
+        return (app, Some(Box::new(hotplug::FakeHotplug::new(block))));
+    }
+
+    let mut app = App::new(config, logs);
+    resume(&mut app);
+
+    match hotplug::SysBlock::new("/sys/block") {
+        Ok(val) => (app, Some(Box::new(val))),
+        Err(ex) => {
+            tracing::warn!(error = %ex, "No hotplug, the device list only updates on request");
+            (app, None)
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+use std::{error::Error, io, path::PathBuf};
+
+use tracing::{error, info, warn};
+use tracing::{
+    field::{Field, Visit},
+    Level, Subscriber,
+};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter, Registry};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::config::{ConfigError, LogOutput, LogRotation, LoggingConfig};
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// How many entries the on-device log screen can go back through
+const RECENT_ENTRIES: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// Increases by one per entry, so screens can point at the one they are
+    /// about
+    pub id: u64,
+    pub level: Level,
+    /// The message followed by its fields, like `Mounted device=sda1`
+    pub text: String,
+}
+
+impl std::fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.level, self.text)
+    }
+}
+
+/// The most recent log entries, kept in memory for the log screen
+///
+/// Clones share the same entries, one is installed as a layer by [`init`]
+/// and the app reads from another.
+#[derive(Clone, Default)]
+pub struct LogBuffer {
+    entries: Arc<Mutex<(u64, VecDeque<LogEntry>)>>,
+}
+
+impl LogBuffer {
+    fn push(&self, level: Level, text: String) {
+        let Ok(mut guard) = self.entries.lock() else {
+            return;
+        };
+        let (next_id, entries) = &mut *guard;
+
+        if entries.len() == RECENT_ENTRIES {
+            entries.pop_front();
+        }
+        entries.push_back(LogEntry {
+            id: *next_id,
+            level,
+            text,
+        });
+        *next_id += 1;
+    }
+
+    /// Id of the newest entry, if anything has been logged yet
+    pub fn latest(&self) -> Option<u64> {
+        let guard = self.entries.lock().ok()?;
+        guard.1.back().map(|x| x.id)
+    }
+
+    /// Copies out the entries, oldest first
+    pub fn entries(&self) -> Vec<LogEntry> {
+        match self.entries.lock() {
+            Ok(guard) => guard.1.iter().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+/// Flattens an event into its message and `name=value` pairs
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            let _ = write!(self.rest, " {}={value:?}", field.name());
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBuffer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        self.push(*event.metadata().level(), fields.message + &fields.rest);
+    }
+}
+
+/// `RUST_LOG` wins over the config, so a one off debug run needs no edits
+fn filter(config: &LoggingConfig) -> EnvFilter {
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        return filter;
+    }
+
+    let mut filter = EnvFilter::try_new(&config.level).unwrap_or_else(|_| EnvFilter::new("info"));
+    for (target, level) in config.targets.iter() {
+        match format!("{target}={level}").parse() {
+            Ok(directive) => filter = filter.add_directive(directive),
+            Err(ex) => eprintln!("Ignoring log level {level} for {target}: {ex}"),
+        }
+    }
+
+    filter
+}
+
+fn file(config: &LoggingConfig) -> Result<(BoxedLayer, WorkerGuard), Box<dyn Error>> {
+    let dir = config.path.parent().map(PathBuf::from).unwrap_or_default();
+    let prefix = config
+        .path
+        .file_name()
+        .ok_or("log path has no file name")?
+        .to_string_lossy()
+        .into_owned();
+
+    let appender = rolling::Builder::new()
+        .rotation(match config.rotation {
+            LogRotation::Hourly => rolling::Rotation::HOURLY,
+            LogRotation::Daily => rolling::Rotation::DAILY,
+            LogRotation::Never => rolling::Rotation::NEVER,
+        })
+        .filename_prefix(prefix)
+        .max_log_files(config.keep.max(1))
+        .build(dir)?;
+
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let layer = fmt::layer().with_ansi(false).with_writer(writer).boxed();
+
+    Ok((layer, guard))
+}
+
+/// Installs the global logger described by `config`, also copying entries
+/// into `recent`
+///
+/// Falls back to stderr if journald or the log file cannot be opened. The
+/// returned guard flushes the log file and has to live until exit.
+pub fn init(config: &LoggingConfig, recent: LogBuffer) -> Option<WorkerGuard> {
+    let stderr = || fmt::layer().with_writer(io::stderr).boxed();
+
+    let (layer, guard, failed): (Option<BoxedLayer>, _, Option<Box<dyn Error>>) =
+        match config.output {
+            LogOutput::None => (None, None, None),
+            LogOutput::Stderr => (Some(stderr()), None, None),
+            LogOutput::Journald => match tracing_journald::layer() {
+                Ok(layer) => (Some(layer.boxed()), None, None),
+                Err(ex) => (Some(stderr()), None, Some(ex.into())),
+            },
+            LogOutput::File => match file(config) {
+                Ok((layer, guard)) => (Some(layer), Some(guard), None),
+                Err(ex) => (Some(stderr()), None, Some(ex)),
+            },
+        };
+
+    // One filter in front of both, so its warnings are only printed once
+    if let Err(ex) = tracing_subscriber::registry()
+        .with(layer)
+        .with(recent)
+        .with(filter(config))
+        .try_init()
+    {
+        eprintln!("Could not set up logging {ex}");
+    }
+
+    if let Some(ex) = failed {
+        warn!(output = ?config.output, error = %ex, "Could not open log output, using stderr");
+    }
+
+    guard
+}
+
+/// Reports how loading the config went, once there is somewhere to log it
+pub fn config_loaded(result: &Result<PathBuf, ConfigError>) {
+    match result {
+        Ok(path) => info!(path = %path.display(), "Loaded config"),
+        Err(ConfigError::NotFound) => warn!("No config file, using defaults"),
+        Err(ex) => error!(error = %ex, "Could not load config, using defaults"),
+    }
+}
@@ -1,149 +1,117 @@
-mod app;
-mod config;
-mod device;
-#[cfg(feature = "simulator")]
-mod simulator;
-
-use app::App;
-
-#[cfg(feature = "simulator")]
-const WIDTH: u32 = 128;
-#[cfg(feature = "simulator")]
-const HEIGHT: u32 = 64;
-#[cfg(feature = "simulator")]
-const SIZE: u32 = 1;
-
-#[cfg(feature = "pi")]
+//! Runs on the Pi, drawing on the OLED and reading the buttons over GPIO
+
+use drive_pi::*;
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use embedded_graphics::prelude::*;
     use futures::stream::StreamExt;
-    use gpio_cdev::{Chip, EventRequestFlags, LineRequestFlags};
+    use gpio_cdev::{Chip, EventRequestFlags, EventType, LineRequestFlags};
+    use input::Button;
     use linux_embedded_hal::I2cdev;
-    use ssd1306::{
-        prelude::*, rotation::DisplayRotation, size::DisplaySize128x64, I2CDisplayInterface,
-        Ssd1306,
-    };
+    use oled::Oled;
 
-    let mut i2c = I2cdev::new("/dev/i2c-1")?;
-
-    i2c.set_slave_address(0x3C)?;
-
-    let interface = I2CDisplayInterface::new(i2c);
-    let mut display = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
-        .into_buffered_graphics_mode();
-
-    display.init().unwrap();
-    display.set_display_on(true).unwrap();
-    display.set_brightness(Brightness::BRIGHTEST).unwrap();
-
-    let mut chip = Chip::new("/dev/gpiochip0").unwrap();
-
-    let mut a_events = chip.get_line(4)?.async_events(
-        LineRequestFlags::INPUT,
-        EventRequestFlags::RISING_EDGE,
-        "drive-pi",
-    )?;
-
-    let mut b_events = chip.get_line(14)?.async_events(
-        LineRequestFlags::INPUT,
-        EventRequestFlags::RISING_EDGE,
-        "drive-pi",
-    )?;
-
-    let mut c_events = chip.get_line(15)?.async_events(
-        LineRequestFlags::INPUT,
-        EventRequestFlags::RISING_EDGE,
-        "drive-pi",
-    )?;
-
-    let mut d_events = chip.get_line(18)?.async_events(
-        LineRequestFlags::INPUT,
-        EventRequestFlags::RISING_EDGE,
-        "drive-pi",
-    )?;
-
-    let mut app = App::default();
-
-    app.load_config();
-
-    app.draw(&mut display).unwrap();
-
-    display.flush().unwrap();
-
-    loop {
-        tokio::select! {
-            Some(Ok(_)) = a_events.next() => {
-                app.handle_input("a");
-                println!("a");
-            }
-            Some(Ok(_)) = b_events.next() => {
-                app.handle_input("b");
-                println!("b");
-            }
-            Some(Ok(_)) = c_events.next() => {
-                app.handle_input("c");
-                println!("c");
-            }
-            Some(Ok(_)) = d_events.next() => {
-                app.handle_input("d");
-                println!("d");
-            }
-        };
-
-        if app.should_exit() {
-            break;
-        }
+    let logs = logging::LogBuffer::default();
+    let (config, log) = startup(&logs, false);
 
-        display.clear();
-        app.draw(&mut display).unwrap();
-        display.flush().unwrap();
+    // Before anything is mounted, so a wiring mistake changes nothing
+    let hardware = config.hardware.clone();
+    if let Err(ex) = hardware.validate() {
+        tracing::error!(error = %ex, "Invalid hardware config");
+        drop(log);
+        std::process::exit(1);
     }
 
-    Ok(())
-}
+    // Opened before the app is built, which mounts drives from the last
+    // run, so missing hardware fails the start with nothing changed
+    let i2c = I2cdev::new(&hardware.i2c_bus)?;
+    let mut display = Oled::new(i2c, &hardware);
 
-#[cfg(feature = "simulator")]
-fn main() {
-    use piston_window::{EventLoop, PistonWindow, Window, WindowSettings};
-    use simulator::ScreenSimulator;
-
-    let mut window: PistonWindow =
-        WindowSettings::new("EG Simulator", [WIDTH * SIZE, HEIGHT * SIZE])
-            .exit_on_esc(true)
-            .resizable(false)
-            //.opengl(OpenGL::V2_1) // Set a different OpenGl version
-            .build()
-            .unwrap();
-
-    let mut display = ScreenSimulator::new();
-
-    window.set_lazy(true);
-
-    let mut app = App::default();
-    app.load_config();
-
-    while let Some(e) = window.next() {
-        match &e {
-            piston_window::Event::Input(i, _) => match i {
-                piston_window::Input::Text(x) => {if x == "q" {window.set_should_close(true);} else {app.handle_input(x);}},
-                _ => {}
-            },
-            _ => {}
-            // piston_window::Event::Loop(_) => todo!(),
-            // piston_window::Event::Custom(_, _, _) => todo!(),
-        }
+    // The event loop re-initialises it when a flush fails, so carry on
+    if let Err(ex) = display.init() {
+        tracing::error!(error = %ex, "Could not initialise display");
+    }
 
-        display.clear(BinaryColor::Off).unwrap();
+    let mut chip = Chip::new(&hardware.gpio_chip)?;
+    set_bias(&hardware)?;
 
-        app.draw(&mut display).unwrap();
+    let mut flags = LineRequestFlags::INPUT;
+    if hardware.active_low {
+        flags |= LineRequestFlags::ACTIVE_LOW;
+    }
+
+    let mut lines = vec![];
+    for button in Button::ALL {
+        let events = chip.get_line(*hardware.lines.get(button))?.async_events(
+            flags,
+            EventRequestFlags::BOTH_EDGES,
+            "drive-pi",
+        )?;
+
+        lines.push(events.filter_map(move |x| {
+            futures::future::ready(match x {
+                Ok(event) => Some((
+                    button,
+                    event.event_type() == EventType::RisingEdge,
+                    event.timestamp(),
+                )),
+                Err(ex) => {
+                    tracing::error!(?button, error = %ex, "Could not read GPIO event");
+                    None
+                }
+            })
+        }));
+    }
 
-        window.draw_2d(&e, |c, g, _| {
-            display.draw(c, g);
-        });
+    let (app, hotplug) = build_app(config, logs);
 
-        if app.should_exit() {
-            window.set_should_close(true)
+    event_loop::run(
+        app,
+        &mut display,
+        futures::stream::select_all(lines),
+        hotplug,
+    )
+    .await
+}
+
+/// Sets the pull on the button lines with the Raspberry Pi's `pinctrl`, or
+/// `raspi-gpio` on older releases
+///
+/// gpio-cdev 0.5 has no bias request flags, and the BCM GPIO keeps its pulls
+/// once set, so they are set before the lines are requested.
+fn set_bias(hardware: &config::HardwareConfig) -> std::io::Result<()> {
+    use config::Bias;
+    use input::Button;
+    use std::{
+        io::{Error, ErrorKind},
+        process::Command,
+    };
+
+    let pull = match hardware.bias {
+        Bias::AsIs => return Ok(()),
+        Bias::PullUp => "pu",
+        Bias::PullDown => "pd",
+        Bias::Disabled => "pn",
+    };
+
+    for button in Button::ALL {
+        let line = hardware.lines.get(button).to_string();
+
+        let set = |tool| Command::new(tool).args(["set", &line, "ip", pull]).output();
+        let output = match set("pinctrl") {
+            Err(ex) if ex.kind() == ErrorKind::NotFound => set("raspi-gpio"),
+            x => x,
+        }
+        .map_err(|ex| match ex.kind() {
+            ErrorKind::NotFound => Error::other("pinctrl or raspi-gpio is needed to set the bias"),
+            _ => ex,
+        })?;
+        if !output.status.success() {
+            return Err(Error::other(format!(
+                "could not set the bias of GPIO {line}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
         }
     }
+
+    Ok(())
 }
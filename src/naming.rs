@@ -0,0 +1,212 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::device::Device;
+
+/// Expands a mount name template, `kernel`, `label` and `uuid` are
+/// shorthands for `{name}`, `{label}` and `{uuid}`
+///
+/// Placeholders are `{name}`, `{label}`, `{uuid}`, `{uuid8}` (the first 8
+/// characters of the UUID) and `{fstype}`. Missing fields expand to nothing
+/// and the kernel name is used if that leaves the name empty.
+pub fn mount_name(template: &str, device: &Device) -> String {
+    let template = match template {
+        "kernel" => "{name}",
+        "label" => "{label}",
+        "uuid" => "{uuid}",
+        x => x,
+    };
+
+    let uuid = device.uuid.clone().unwrap_or_default();
+    let name = template
+        .replace("{name}", &device.name)
+        .replace("{label}", device.label.as_deref().unwrap_or_default())
+        .replace("{uuid8}", &uuid.chars().take(8).collect::<String>())
+        .replace("{uuid}", &uuid)
+        .replace("{fstype}", device.fstype.as_deref().unwrap_or_default());
+
+    let name = sanitise(&name);
+    if name.is_empty() {
+        sanitise(&device.name)
+    } else {
+        name
+    }
+}
+
+/// Keeps the name to a single safe path component
+fn sanitise(name: &str) -> String {
+    name.chars()
+        .map(|x| {
+            if x.is_ascii_alphanumeric() || x == '-' || x == '_' || x == '.' {
+                x
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_matches(|x| x == '-' || x == '_' || x == '.')
+        .to_string()
+}
+
+/// Gives every unmounted device a mount path under `mount_point`
+///
+/// Mounted devices keep the path they are mounted at, so a rebuilt list still
+/// matches what is on disk. The rest get names unique across the whole list
+/// and anything already in `mount_point` other than the mount points in
+/// `created`, which drive-pi made itself. Clashing names get `-2`, `-3`, ...
+/// appended.
+pub fn assign_paths(
+    devices: &mut [Device],
+    mount_point: &Path,
+    template: &str,
+    created: &BTreeSet<PathBuf>,
+) {
+    let mut taken: HashSet<_> = devices
+        .iter()
+        .filter(|x| x.mounted)
+        .map(|x| x.path.clone())
+        .collect();
+
+    for device in devices.iter_mut().filter(|x| !x.mounted) {
+        let base = mount_name(template, device);
+        let mut path = mount_point.join(&base);
+        let mut suffix = 2;
+
+        // Mounting over a directory that is already there would hide it
+        while taken.contains(&path) || (path.exists() && !created.contains(&path)) {
+            path = mount_point.join(format!("{base}-{suffix}"));
+            suffix += 1;
+        }
+
+        taken.insert(path.clone());
+        device.path = path;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::tempdir::TempDir;
+
+    fn device(name: &str, label: Option<&str>) -> Device {
+        Device {
+            name: name.to_string(),
+            label: label.map(str::to_string),
+            uuid: Some("1234abcd-56ef".to_string()),
+            fstype: Some("vfat".to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn names(devices: &[Device], root: &Path) -> Vec<PathBuf> {
+        devices
+            .iter()
+            .map(|x| x.path.strip_prefix(root).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn templates() {
+        let stick = device("sda1", Some("STICK"));
+
+        assert_eq!(mount_name("kernel", &stick), "sda1");
+        assert_eq!(mount_name("label", &stick), "STICK");
+        assert_eq!(mount_name("uuid", &stick), "1234abcd-56ef");
+        assert_eq!(mount_name("{uuid8}", &stick), "1234abcd");
+        assert_eq!(mount_name("{label}-{fstype}", &stick), "STICK-vfat");
+        assert_eq!(mount_name("usb-{name}", &stick), "usb-sda1");
+    }
+
+    #[test]
+    fn missing_fields() {
+        let bare = Device {
+            name: "sdb1".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(mount_name("label", &bare), "sdb1");
+        assert_eq!(mount_name("{uuid8}", &bare), "sdb1");
+        assert_eq!(mount_name("{label}-{name}", &bare), "sdb1");
+        assert_eq!(mount_name("{name}-{label}", &bare), "sdb1");
+    }
+
+    #[test]
+    fn sanitising() {
+        assert_eq!(mount_name("label", &device("sda1", Some("a/b"))), "a_b");
+        assert_eq!(mount_name("label", &device("sda1", Some(".."))), "sda1");
+        assert_eq!(mount_name("label", &device("sda1", Some("../etc"))), "etc");
+        assert_eq!(mount_name("label", &device("sda1", Some(""))), "sda1");
+        assert_eq!(mount_name("label", &device("sda1", Some("  "))), "sda1");
+        assert_eq!(
+            mount_name("label", &device("sda1", Some("My Photos!"))),
+            "My_Photos"
+        );
+    }
+
+    #[test]
+    fn collision_suffixes() {
+        let root = TempDir::new("naming-suffix").unwrap();
+        let mut devices = vec![
+            device("sda1", Some("STICK")),
+            device("sdb1", Some("STICK")),
+            device("sdc1", Some("STICK")),
+        ];
+
+        assign_paths(&mut devices, root.path(), "label", &BTreeSet::new());
+
+        assert_eq!(
+            names(&devices, root.path()),
+            vec![
+                PathBuf::from("STICK"),
+                PathBuf::from("STICK-2"),
+                PathBuf::from("STICK-3")
+            ]
+        );
+    }
+
+    #[test]
+    fn mounted_paths_are_kept() {
+        let root = TempDir::new("naming-mounted").unwrap();
+        let mut devices = vec![device("sda1", Some("STICK")), device("sdb1", Some("STICK"))];
+        assign_paths(&mut devices, root.path(), "label", &BTreeSet::new());
+
+        // Mounting the second one leaves the first one where it was
+        devices[1].mounted = true;
+        fs::create_dir(&devices[1].path).unwrap();
+        let before = names(&devices, root.path());
+        assign_paths(&mut devices, root.path(), "label", &BTreeSet::new());
+
+        assert_eq!(names(&devices, root.path()), before);
+    }
+
+    #[test]
+    fn existing_directories_are_skipped() {
+        let root = TempDir::new("naming-existing").unwrap();
+        fs::create_dir(root.path().join("STICK")).unwrap();
+        fs::write(root.path().join("STICK-2"), "").unwrap();
+        let mut devices = vec![device("sda1", Some("STICK"))];
+
+        assign_paths(&mut devices, root.path(), "label", &BTreeSet::new());
+
+        assert_eq!(names(&devices, root.path()), vec![PathBuf::from("STICK-3")]);
+    }
+
+    #[test]
+    fn created_directories_are_reused() {
+        let root = TempDir::new("naming-created").unwrap();
+        let created = BTreeSet::from([root.path().join("STICK")]);
+        fs::create_dir(root.path().join("STICK")).unwrap();
+        let mut devices = vec![device("sda1", Some("STICK")), device("sdb1", Some("STICK"))];
+
+        assign_paths(&mut devices, root.path(), "label", &created);
+
+        assert_eq!(
+            names(&devices, root.path()),
+            vec![PathBuf::from("STICK"), PathBuf::from("STICK-2")]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use linux_embedded_hal::I2cdev;
+use sh1106::{interface::I2cInterface, mode::GraphicsMode};
+use ssd1306::{
+    mode::BufferedGraphicsMode,
+    prelude::*,
+    size::{DisplaySize128x32, DisplaySize128x64},
+    I2CDisplayInterface, Ssd1306,
+};
+
+use crate::{
+    config::{Controller, DisplaySize, HardwareConfig, Rotation},
+    event_loop::Flush,
+};
+
+type Ssd1306Display<S> = Ssd1306<I2CInterface<I2cdev>, S, BufferedGraphicsMode<S>>;
+
+#[derive(Debug)]
+pub struct OledError(String);
+
+impl std::fmt::Display for OledError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "display error {}", self.0)
+    }
+}
+
+impl std::error::Error for OledError {}
+
+fn oled_error<E: std::fmt::Debug>(ex: E) -> OledError {
+    OledError(format!("{ex:?}"))
+}
+
+/// The OLED picked in the `[hardware]` config, whichever controller it is
+pub enum Oled {
+    Ssd1306x64(Ssd1306Display<DisplaySize128x64>),
+    Ssd1306x32(Ssd1306Display<DisplaySize128x32>),
+    Sh1106(GraphicsMode<I2cInterface<I2cdev>>),
+}
+
+impl Oled {
+    pub fn new(i2c: I2cdev, config: &HardwareConfig) -> Self {
+        let address = config.i2c_address as u8;
+
+        match config.controller {
+            // The SSD1309 takes the same commands as the SSD1306 and ignores
+            // the charge pump setup it does not have
+            Controller::Ssd1306 | Controller::Ssd1309 => {
+                let interface = I2CDisplayInterface::new_custom_address(i2c, address);
+                let rotation = match config.rotation {
+                    Rotation::Rotate0 => DisplayRotation::Rotate0,
+                    Rotation::Rotate90 => DisplayRotation::Rotate90,
+                    Rotation::Rotate180 => DisplayRotation::Rotate180,
+                    Rotation::Rotate270 => DisplayRotation::Rotate270,
+                };
+
+                match config.display_size {
+                    DisplaySize::Size128x64 => Oled::Ssd1306x64(
+                        Ssd1306::new(interface, DisplaySize128x64, rotation)
+                            .into_buffered_graphics_mode(),
+                    ),
+                    DisplaySize::Size128x32 => Oled::Ssd1306x32(
+                        Ssd1306::new(interface, DisplaySize128x32, rotation)
+                            .into_buffered_graphics_mode(),
+                    ),
+                }
+            }
+            Controller::Sh1106 => {
+                use sh1106::{displayrotation::DisplayRotation, displaysize::DisplaySize as Size};
+
+                let rotation = match config.rotation {
+                    Rotation::Rotate0 => DisplayRotation::Rotate0,
+                    Rotation::Rotate90 => DisplayRotation::Rotate90,
+                    Rotation::Rotate180 => DisplayRotation::Rotate180,
+                    Rotation::Rotate270 => DisplayRotation::Rotate270,
+                };
+                let size = match config.display_size {
+                    DisplaySize::Size128x64 => Size::Display128x64,
+                    DisplaySize::Size128x32 => Size::Display128x32,
+                };
+
+                Oled::Sh1106(
+                    sh1106::Builder::new()
+                        .with_size(size)
+                        .with_rotation(rotation)
+                        .with_i2c_addr(address)
+                        .connect_i2c(i2c)
+                        .into(),
+                )
+            }
+        }
+    }
+
+    /// Initialises the controller and turns the display on at full brightness
+    pub fn init(&mut self) -> Result<(), OledError> {
+        match self {
+            Oled::Ssd1306x64(display) => {
+                display.init().map_err(oled_error)?;
+                display.set_display_on(true).map_err(oled_error)?;
+                display
+                    .set_brightness(Brightness::BRIGHTEST)
+                    .map_err(oled_error)
+            }
+            Oled::Ssd1306x32(display) => {
+                display.init().map_err(oled_error)?;
+                display.set_display_on(true).map_err(oled_error)?;
+                display
+                    .set_brightness(Brightness::BRIGHTEST)
+                    .map_err(oled_error)
+            }
+            Oled::Sh1106(display) => {
+                display.init().map_err(oled_error)?;
+                display.set_contrast(0xFF).map_err(oled_error)
+            }
+        }
+    }
+}
+
+impl Flush for Oled {
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Oled::Ssd1306x64(display) => display.flush().map_err(oled_error)?,
+            Oled::Ssd1306x32(display) => display.flush().map_err(oled_error)?,
+            Oled::Sh1106(display) => display.flush().map_err(oled_error)?,
+        };
+
+        Ok(())
+    }
+
+    fn reinit(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.init()?)
+    }
+}
+
+impl OriginDimensions for Oled {
+    fn size(&self) -> Size {
+        match self {
+            Oled::Ssd1306x64(display) => display.size(),
+            Oled::Ssd1306x32(display) => display.size(),
+            Oled::Sh1106(display) => display.size(),
+        }
+    }
+}
+
+impl DrawTarget for Oled {
+    type Color = BinaryColor;
+
+    type Error = OledError;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        match self {
+            Oled::Ssd1306x64(display) => display.draw_iter(pixels).map_err(oled_error),
+            Oled::Ssd1306x32(display) => display.draw_iter(pixels).map_err(oled_error),
+            Oled::Sh1106(display) => display.draw_iter(pixels).map_err(oled_error),
+        }
+    }
+
+    /// Clears the buffer directly, rather than a pixel at a time
+    fn clear(&mut self, colour: Self::Color) -> Result<(), Self::Error> {
+        match (self, colour) {
+            (Oled::Ssd1306x64(display), BinaryColor::Off) => display.clear(),
+            (Oled::Ssd1306x32(display), BinaryColor::Off) => display.clear(),
+            (Oled::Sh1106(display), BinaryColor::Off) => display.clear(),
+            (display, BinaryColor::On) => {
+                return display.fill_solid(&display.bounding_box(), BinaryColor::On)
+            }
+        }
+
+        Ok(())
+    }
+}
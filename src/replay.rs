@@ -0,0 +1,404 @@
+//! Drives the app from a script, against the fake backends and an in memory
+//! display, so whole flows can be checked without a Pi
+//!
+//! Scripts have one step per line, blank lines and lines starting with `#`
+//! are ignored. Buttons use the config names, like `top_left`.
+//!
+//! ```text
+//! plug sdb1 32G label=USB fstype=vfat
+//! press top_right
+//! expect screen devices
+//! long bottom_right
+//! wait 3000
+//! expect frame home.txt
+//! ```
+//!
+//! * `press`, `long`, `repeat <button>` and `chord <button> <button>`
+//! * `wait <ms>` moves the clock on and lets timers fire
+//! * `plug <name> <size> [label=..] [uuid=..] [fstype=..]` and `unplug <name>`
+//! * `expect screen <name>`, `expect mounted|unmounted <device>` and
+//!   `expect shared|unshared <share>`
+//! * `expect frame <file>` compares the display against an ASCII snapshot, a
+//!   missing snapshot fails unless the script is run with `--update`, which
+//!   (re)writes it instead
+//! * `snapshot <file>` saves the display as PNG, PBM or ASCII
+
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use tracing::info;
+
+use crate::{
+    app::App,
+    config::Config,
+    device::{Device, FakeBlockDevices},
+    framebuffer::FrameBuffer,
+    hotplug::{FakeHotplug, HotplugSource},
+    input::{Button, Event},
+    smb::FakeSmb,
+    tempdir::TempDir,
+    wifi::FakeWifi,
+};
+
+#[derive(Debug)]
+pub struct ReplayError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl Error for ReplayError {}
+
+enum Step {
+    Input(Event),
+    Wait(Duration),
+    Plug(Box<Device>),
+    Unplug(String),
+    ExpectScreen(String),
+    ExpectMounted(String, bool),
+    ExpectShared(String, bool),
+    ExpectFrame(PathBuf),
+    Snapshot(PathBuf),
+}
+
+fn parse_step(line: &str) -> Result<Step, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let mut arg = || {
+        words
+            .next()
+            .ok_or_else(|| format!("{command} is missing an argument"))
+    };
+
+    let step = match command {
+        "press" => Step::Input(Event::Press(arg()?.parse()?)),
+        "long" => Step::Input(Event::LongPress(arg()?.parse()?)),
+        "repeat" => Step::Input(Event::Repeat(arg()?.parse()?)),
+        "chord" => {
+            let first: Button = arg()?.parse()?;
+            Step::Input(Event::Chord(first, arg()?.parse()?))
+        }
+        "wait" => {
+            let ms = arg()?.parse().map_err(|ex| format!("invalid wait: {ex}"))?;
+            Step::Wait(Duration::from_millis(ms))
+        }
+        "plug" => {
+            let mut device = Device {
+                name: arg()?.to_string(),
+                size: arg()?.to_string(),
+                ..Default::default()
+            };
+
+            for word in words {
+                match word.split_once('=') {
+                    Some(("label", val)) => device.label = Some(val.to_string()),
+                    Some(("uuid", val)) => device.uuid = Some(val.to_string()),
+                    Some(("fstype", val)) => device.fstype = Some(val.to_string()),
+                    _ => return Err(format!("unknown device property {word}")),
+                }
+            }
+
+            Step::Plug(Box::new(device))
+        }
+        "unplug" => Step::Unplug(arg()?.to_string()),
+        "expect" => match arg()? {
+            "screen" => Step::ExpectScreen(arg()?.to_string()),
+            "mounted" => Step::ExpectMounted(arg()?.to_string(), true),
+            "unmounted" => Step::ExpectMounted(arg()?.to_string(), false),
+            "shared" => Step::ExpectShared(arg()?.to_string(), true),
+            "unshared" => Step::ExpectShared(arg()?.to_string(), false),
+            "frame" => Step::ExpectFrame(PathBuf::from(arg()?)),
+            other => return Err(format!("unknown expectation {other}")),
+        },
+        "snapshot" => Step::Snapshot(PathBuf::from(arg()?)),
+        other => return Err(format!("unknown command {other}")),
+    };
+
+    Ok(step)
+}
+
+/// Parses the whole script up front, so a typo fails before anything runs
+fn parse(source: &str) -> Result<Vec<(usize, Step)>, ReplayError> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, source)| {
+            parse_step(source)
+                .map(|step| (line, step))
+                .map_err(|msg| ReplayError { line, msg })
+        })
+        .collect()
+}
+
+struct Replay {
+    app: App,
+    block: Rc<RefCell<FakeBlockDevices>>,
+    hotplug: FakeHotplug,
+    smb: Rc<RefCell<FakeSmb>>,
+    display: FrameBuffer,
+    /// How far `wait` steps have moved the clock ahead of real time
+    offset: Duration,
+    /// Relative snapshot paths are resolved against the script
+    dir: PathBuf,
+    /// Write frame snapshots instead of comparing against them
+    update: bool,
+    // Last, so the app has tidied up before the directory goes
+    _root: TempDir,
+}
+
+impl Replay {
+    fn new(dir: PathBuf, update: bool) -> io::Result<Self> {
+        let block = Rc::new(RefCell::new(FakeBlockDevices::default()));
+        let smb = Rc::new(RefCell::new(FakeSmb::default()));
+
+        let mut app = App::with_backends(
+            Box::new(block.clone()),
+            Box::new(FakeWifi::default()),
+            Box::new(smb.clone()),
+        );
+
+        // Nothing is really mounted, but the app still tidies its mount root
+        let root = TempDir::new("replay")?;
+        let mut config = Config {
+            mount_path: root.path().to_path_buf(),
+            ..Default::default()
+        };
+        config.smb.conf_path = config.mount_path.join("smb.conf");
+        config.state_path = config.mount_path.join("state.json");
+
+        let display = FrameBuffer::for_config(&config.hardware);
+        app.set_config(config);
+
+        Ok(Self {
+            app,
+            hotplug: FakeHotplug::new(block.clone()),
+            block,
+            smb,
+            display,
+            offset: Duration::ZERO,
+            dir,
+            update,
+            _root: root,
+        })
+    }
+
+    fn event(&mut self, event: Event) {
+        self.app.handle_event(event);
+    }
+
+    /// Passes on whatever plugging and unplugging the last step did
+    fn hotplug(&mut self) {
+        for event in self.hotplug.poll().unwrap_or_default() {
+            self.event(Event::Hotplug(event));
+        }
+    }
+
+    fn frame(&mut self) -> &FrameBuffer {
+        self.display.clear(BinaryColor::Off).unwrap();
+        self.app.draw(&mut self.display).unwrap();
+        &self.display
+    }
+
+    fn run(&mut self, step: Step) -> Result<(), String> {
+        match step {
+            Step::Input(event) => self.event(event),
+            Step::Wait(duration) => {
+                self.offset += duration;
+                self.event(Event::Tick(Instant::now() + self.offset));
+            }
+            Step::Plug(device) => {
+                self.block.borrow_mut().partitions.push(*device);
+                self.hotplug();
+            }
+            Step::Unplug(name) => {
+                self.block.borrow_mut().unplug(&name);
+                self.hotplug();
+            }
+            Step::ExpectScreen(name) => {
+                let current = self.app.screen_name();
+                if current != name {
+                    return Err(format!("expected screen {name}, found {current}"));
+                }
+            }
+            Step::ExpectMounted(name, expected) => {
+                let mounted = self
+                    .block
+                    .borrow()
+                    .partitions
+                    .iter()
+                    .find(|x| x.name == name)
+                    .map(|x| x.mounted)
+                    .ok_or(format!("no device {name}"))?;
+
+                if mounted != expected {
+                    return Err(format!("expected {name} mounted to be {expected}"));
+                }
+            }
+            Step::ExpectShared(name, expected) => {
+                let shared = self
+                    .smb
+                    .borrow()
+                    .configs
+                    .last()
+                    .map(|(_, contents)| contents.contains(&format!("[{name}]")))
+                    .unwrap_or(false);
+
+                if shared != expected {
+                    return Err(format!("expected {name} shared to be {expected}"));
+                }
+            }
+            Step::ExpectFrame(path) => {
+                let path = self.dir.join(path);
+                let actual = self.frame().to_ascii();
+
+                if self.update {
+                    fs::write(&path, &actual).map_err(|ex| ex.to_string())?;
+                    info!(path = %path.display(), "Wrote snapshot");
+                    return Ok(());
+                }
+
+                match fs::read_to_string(&path) {
+                    Ok(expected) if expected == actual => {}
+                    Ok(_) => {
+                        let actual_path = path.with_extension("actual");
+                        let _ = fs::write(&actual_path, &actual);
+                        return Err(format!(
+                            "frame does not match {}, got {}",
+                            path.display(),
+                            actual_path.display()
+                        ));
+                    }
+                    Err(ex) => {
+                        return Err(format!(
+                            "could not read {}: {ex}, run with --update to write it",
+                            path.display()
+                        ));
+                    }
+                }
+            }
+            Step::Snapshot(path) => {
+                let path = self.dir.join(path);
+                self.frame().save(&path).map_err(|ex| ex.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the script at `path`, failing on the first step that does not hold
+///
+/// With `update` set frame snapshots are written rather than checked.
+pub fn run(path: &Path, update: bool) -> Result<(), Box<dyn Error>> {
+    let steps = parse(&fs::read_to_string(path)?)?;
+
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut replay = Replay::new(dir, update)?;
+
+    for (line, step) in steps {
+        replay.run(step).map_err(|msg| ReplayError { line, msg })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: &str) -> String {
+        match parse_step(line) {
+            Ok(_) => panic!("{line} parsed"),
+            Err(ex) => ex,
+        }
+    }
+
+    #[test]
+    fn scripts_pass() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts");
+        let mut count = 0;
+
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|x| x.to_str()) != Some("txt") {
+                continue;
+            }
+
+            if let Err(ex) = run(&path, false) {
+                panic!("{}: {ex}", path.display());
+            }
+            count += 1;
+        }
+
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn unknown_command() {
+        assert_eq!(error("jump top_left"), "unknown command jump");
+        assert_eq!(error("expect flying"), "unknown expectation flying");
+        assert_eq!(
+            error("plug sdb1 32G colour=red"),
+            "unknown device property colour=red"
+        );
+    }
+
+    #[test]
+    fn missing_argument() {
+        assert_eq!(error("press"), "press is missing an argument");
+        assert_eq!(error("chord top_left"), "chord is missing an argument");
+        assert_eq!(error("plug sdb1"), "plug is missing an argument");
+    }
+
+    #[test]
+    fn unknown_button() {
+        assert_eq!(error("press middle"), "unknown button middle");
+        assert_eq!(error("chord top_left up"), "unknown button up");
+    }
+
+    #[test]
+    fn bad_duration() {
+        assert_eq!(error("wait"), "wait is missing an argument");
+        assert_eq!(
+            error("wait soon"),
+            "invalid wait: invalid digit found in string"
+        );
+        assert_eq!(
+            error("wait -5"),
+            "invalid wait: invalid digit found in string"
+        );
+        assert_eq!(
+            error("wait 1.5"),
+            "invalid wait: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn error_line_counts_skipped_lines() {
+        let source = "# comment\n\npress top_left\n  wait x\n";
+
+        let Err(ex) = parse(source) else {
+            panic!("script parsed");
+        };
+
+        assert_eq!(ex.line, 4);
+        assert_eq!(
+            ex.to_string(),
+            "line 4: invalid wait: invalid digit found in string"
+        );
+    }
+}
@@ -1,49 +1,247 @@
+use std::collections::HashSet;
+
 use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
-use piston_window::{clear, rectangle, Context, G2d};
+use piston_window::{
+    clear, ellipse, rectangle, Context, G2d, MouseButton, MouseCursorEvent, PressEvent,
+    ReleaseEvent,
+};
+
+use crate::{framebuffer::FrameBuffer, input::Button};
+
+/// Space either side of the display for the buttons, in display pixels
+const SIDE: u32 = 16;
+/// Space above and below the display, in display pixels
+const EDGE: u32 = 4;
+/// Diameter of the on-screen buttons, in display pixels
+const BUTTON: u32 = 10;
+
+const BACKGROUND: [f32; 4] = [0.08, 0.08, 0.08, 1.0];
+const PANEL: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const LIT: [f32; 4] = [0.75, 0.9, 1.0, 1.0];
+const GLOW: [f32; 4] = [0.75, 0.9, 1.0, 0.12];
+const GRID: [f32; 4] = [0.12, 0.12, 0.12, 1.0];
+const BUTTON_UP: [f32; 4] = [0.35, 0.35, 0.35, 1.0];
+const BUTTON_DOWN: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelStyle {
+    /// Square pixels, edge to edge
+    Flat,
+    /// Pixels with a gap between them, like looking at the panel up close
+    Grid,
+    /// Pixels with a soft halo, closer to how a lit OLED looks
+    Glow,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SimulatorOptions {
+    /// Window pixels per display pixel
+    pub zoom: u32,
+    pub style: PixelStyle,
+}
+
+impl Default for SimulatorOptions {
+    fn default() -> Self {
+        Self {
+            zoom: 4,
+            style: PixelStyle::Flat,
+        }
+    }
+}
 
-use crate::{HEIGHT, SIZE, WIDTH};
+impl SimulatorOptions {
+    /// Reads `--zoom <n>`, `--grid` and `--glow` from the command line
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Self {
+        let mut options = Self::default();
 
-const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--zoom" => {
+                    if let Some(zoom) = args.next().and_then(|x| x.parse().ok()) {
+                        options.zoom = u32::max(zoom, 1);
+                    }
+                }
+                "--grid" => options.style = PixelStyle::Grid,
+                "--glow" => options.style = PixelStyle::Glow,
+                _ => {}
+            }
+        }
 
-struct Px {
-    x: u32,
-    y: u32,
+        options
+    }
 }
 
+/// Draws the display and its four corner buttons in a piston window
+///
+/// Drawing goes into a fixed size frame buffer, the window only shows it.
+/// Clicking the buttons reports presses and releases like the GPIO lines.
 pub struct ScreenSimulator {
-    px_buffer: Vec<Px>,
+    frame: FrameBuffer,
+    options: SimulatorOptions,
+    cursor: [f64; 2],
+    pressed: HashSet<Button>,
 }
 
 impl ScreenSimulator {
-    pub fn new() -> Self {
-        Self { px_buffer: vec![] }
+    pub fn new(frame: FrameBuffer, options: SimulatorOptions) -> Self {
+        Self {
+            frame,
+            options,
+            cursor: [0.0, 0.0],
+            pressed: HashSet::new(),
+        }
+    }
+
+    /// Window size that fits the display and the buttons
+    pub fn window_size(&self) -> [u32; 2] {
+        let Size { width, height } = self.frame.size();
+
+        [
+            (width + 2 * SIDE) * self.options.zoom,
+            (height + 2 * EDGE) * self.options.zoom,
+        ]
+    }
+
+    /// Replaces the frame with one drawn elsewhere
+    #[cfg(feature = "pi")]
+    pub fn show(&mut self, frame: &FrameBuffer) {
+        self.frame.clone_from(frame);
+    }
+
+    /// Top left of `button`, in display pixels from the window corner
+    fn button_origin(&self, button: Button) -> (u32, u32) {
+        let Size { width, height } = self.frame.size();
+        let left = (SIDE - BUTTON) / 2;
+        let right = SIDE + width + left;
+        // Level with the corner labels on the display
+        let top = EDGE + 1;
+        let bottom = EDGE + height - BUTTON - 1;
+
+        match button {
+            Button::TopLeft => (left, top),
+            Button::TopRight => (right, top),
+            Button::BottomLeft => (left, bottom),
+            Button::BottomRight => (right, bottom),
+        }
+    }
+
+    fn button_at(&self, [x, y]: [f64; 2]) -> Option<Button> {
+        let zoom = self.options.zoom as f64;
+
+        Button::ALL.into_iter().find(|button| {
+            let (left, top) = self.button_origin(*button);
+            let radius = BUTTON as f64 * zoom / 2.0;
+            let centre_x = left as f64 * zoom + radius;
+            let centre_y = top as f64 * zoom + radius;
+
+            (x - centre_x).powi(2) + (y - centre_y).powi(2) <= radius.powi(2)
+        })
+    }
+
+    /// Tracks the mouse, returning the buttons that went down or up
+    pub fn handle<E>(&mut self, event: &E) -> Vec<(Button, bool)>
+    where
+        E: MouseCursorEvent + PressEvent + ReleaseEvent,
+    {
+        let mut edges = vec![];
+
+        if let Some(cursor) = event.mouse_cursor_args() {
+            self.cursor = cursor;
+
+            // Dragging off a button lets go of it
+            let hovered = self.button_at(cursor);
+            for button in Button::ALL {
+                if self.pressed.contains(&button) && hovered != Some(button) {
+                    self.pressed.remove(&button);
+                    edges.push((button, false));
+                }
+            }
+        }
+
+        if let Some(piston_window::Button::Mouse(MouseButton::Left)) = event.press_args() {
+            if let Some(button) = self.button_at(self.cursor) {
+                if self.pressed.insert(button) {
+                    edges.push((button, true));
+                }
+            }
+        }
+
+        if let Some(piston_window::Button::Mouse(MouseButton::Left)) = event.release_args() {
+            for button in self.pressed.drain() {
+                edges.push((button, false));
+            }
+        }
+
+        edges
     }
 
     pub fn draw(&self, c: Context, g: &mut G2d) {
-        for Px { x, y } in self.px_buffer.iter() {
-            rectangle(
-                WHITE,
+        let zoom = self.options.zoom as f64;
+        let Size { width, height } = self.frame.size();
+        let (left, top) = (SIDE as f64 * zoom, EDGE as f64 * zoom);
+
+        clear(BACKGROUND, g);
+
+        rectangle(
+            PANEL,
+            [left, top, width as f64 * zoom, height as f64 * zoom],
+            c.transform,
+            g,
+        );
+
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let lit = self.frame.get(Point::new(x, y));
+                let px = [left + x as f64 * zoom, top + y as f64 * zoom, zoom, zoom];
+
+                match (self.options.style, lit) {
+                    (PixelStyle::Flat, true) => rectangle(LIT, px, c.transform, g),
+                    (PixelStyle::Grid, true) => {
+                        rectangle(LIT, inset(px, zoom / 8.0), c.transform, g)
+                    }
+                    (PixelStyle::Grid, false) => {
+                        rectangle(GRID, inset(px, zoom / 8.0), c.transform, g)
+                    }
+                    (PixelStyle::Glow, true) => {
+                        rectangle(GLOW, inset(px, -zoom / 2.0), c.transform, g);
+                        rectangle(LIT, inset(px, zoom / 10.0), c.transform, g);
+                    }
+                    (_, false) => {}
+                }
+            }
+        }
+
+        for button in Button::ALL {
+            let (x, y) = self.button_origin(button);
+            let colour = if self.pressed.contains(&button) {
+                BUTTON_DOWN
+            } else {
+                BUTTON_UP
+            };
+
+            ellipse(
+                colour,
                 [
-                    (x * SIZE) as f64,
-                    (y * SIZE) as f64,
-                    SIZE as f64,
-                    SIZE as f64,
+                    x as f64 * zoom,
+                    y as f64 * zoom,
+                    BUTTON as f64 * zoom,
+                    BUTTON as f64 * zoom,
                 ],
                 c.transform,
                 g,
             );
         }
-
-        clear([0.0, 0.0, 0.0, 0.0], g);
     }
 }
 
+/// Shrinks a rectangle by `by` on every side, or grows it if negative
+fn inset([x, y, w, h]: [f64; 4], by: f64) -> [f64; 4] {
+    [x + by, y + by, w - 2.0 * by, h - 2.0 * by]
+}
+
 impl OriginDimensions for ScreenSimulator {
     fn size(&self) -> Size {
-        Size {
-            height: HEIGHT,
-            width: WIDTH,
-        }
+        self.frame.size()
     }
 }
 
@@ -54,32 +252,56 @@ impl DrawTarget for ScreenSimulator {
 
     fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
     where
-        I: IntoIterator<Item = embedded_graphics::Pixel<Self::Color>>,
+        I: IntoIterator<Item = Pixel<Self::Color>>,
     {
-        for Pixel(point, colour) in pixels {
-            if point.x >= 0 && point.x < WIDTH as i32 && point.y >= 0 && point.y < HEIGHT as i32 {
-                if colour.is_on() {
-                    self.px_buffer.push(Px {
-                        x: point.x as u32,
-                        y: point.y as u32,
-                    });
-                }
-            }
-        }
+        self.frame.draw_iter(pixels)
+    }
 
-        Ok(())
+    fn clear(&mut self, colour: Self::Color) -> Result<(), Self::Error> {
+        self.frame.clear(colour)
+    }
+}
+
+/// Frame buffer shared with the window, for drawing from the app thread
+/// when running under the Pi event loop
+#[cfg(feature = "pi")]
+pub struct SharedFrame {
+    pub frame: FrameBuffer,
+    pub shared: std::sync::Arc<std::sync::Mutex<FrameBuffer>>,
+}
+
+#[cfg(feature = "pi")]
+impl OriginDimensions for SharedFrame {
+    fn size(&self) -> Size {
+        self.frame.size()
+    }
+}
+
+#[cfg(feature = "pi")]
+impl DrawTarget for SharedFrame {
+    type Color = BinaryColor;
+
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        self.frame.draw_iter(pixels)
     }
 
     fn clear(&mut self, colour: Self::Color) -> Result<(), Self::Error> {
-        self.px_buffer = Vec::with_capacity((WIDTH * HEIGHT) as usize);
+        self.frame.clear(colour)
+    }
+}
 
-        if colour.is_on() {
-            for x in 0..WIDTH {
-                for y in 0..HEIGHT {
-                    self.px_buffer.push(Px { x, y })
-                }
-            }
-        }
+#[cfg(feature = "pi")]
+impl crate::event_loop::Flush for SharedFrame {
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.shared
+            .lock()
+            .map_err(|_| "simulator window went away")?
+            .clone_from(&self.frame);
 
         Ok(())
     }
@@ -0,0 +1,285 @@
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::SmbConfig, device::Device};
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Share {
+    pub name: String,
+    pub path: PathBuf,
+    pub shared: bool,
+    /// Exported read only, follows how the device is mounted
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+/// Controls the Samba daemon, implemented by [`Smbd`] on the Pi
+pub trait SmbBackend {
+    fn write_config(&mut self, path: &Path, contents: &str) -> io::Result<()>;
+    fn reload(&mut self) -> io::Result<()>;
+    fn set_password(&mut self, username: &str, password: &str) -> io::Result<()>;
+}
+
+/// Samba backend that writes the config fragment to disk and talks to smbd
+/// through `smbcontrol` and `smbpasswd`
+pub struct Smbd;
+
+impl SmbBackend for Smbd {
+    fn write_config(&mut self, path: &Path, contents: &str) -> io::Result<()> {
+        fs::write(path, contents)
+    }
+
+    fn reload(&mut self) -> io::Result<()> {
+        let output = Command::new("smbcontrol")
+            .arg("smbd")
+            .arg("reload-config")
+            .output()?;
+
+        if !output.status.success() {
+            return Err(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn set_password(&mut self, username: &str, password: &str) -> io::Result<()> {
+        let mut child = Command::new("smbpasswd")
+            .arg("-s")
+            .arg("-a")
+            .arg(username)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            write!(stdin, "{password}\n{password}\n")?;
+        }
+
+        let output = child.wait_with_output()?;
+
+        if !output.status.success() {
+            return Err(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// One share per device, only mounted devices are actually exported
+pub fn shares(devices: &[Device]) -> Vec<Share> {
+    devices
+        .iter()
+        .map(|dev| Share {
+            // The mount directory name is stable across replugs, unlike sdX
+            name: dev
+                .path
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned())
+                .unwrap_or_else(|| dev.name.clone()),
+            path: dev.path.clone(),
+            shared: dev.mounted,
+            read_only: dev.read_only(),
+        })
+        .collect()
+}
+
+pub fn generate_config(shares: &[Share], config: &SmbConfig) -> String {
+    let mut out = String::from("# Generated by drive-pi, changes will be overwritten\n");
+
+    for share in shares.iter().filter(|x| x.shared) {
+        out.push_str(&format!(
+            "\n[{}]\n   path = {}\n   valid users = {}\n   read only = {}\n   browseable = yes\n",
+            share.name,
+            share.path.display(),
+            config.username,
+            if share.read_only { "yes" } else { "no" },
+        ));
+    }
+
+    out
+}
+
+/// Regenerates the config fragment for `devices` and reloads smbd, returning
+/// the shares that are now exported
+pub fn update(
+    backend: &mut dyn SmbBackend,
+    devices: &[Device],
+    config: &SmbConfig,
+) -> io::Result<Vec<Share>> {
+    let shares = shares(devices);
+    let contents = generate_config(&shares, config);
+    backend.write_config(&config.conf_path, &contents)?;
+    backend.reload()?;
+
+    Ok(shares.into_iter().filter(|x| x.shared).collect())
+}
+
+/// In memory Samba backend that records everything it is asked to do
+#[cfg(any(test, feature = "fake"))]
+#[derive(Default)]
+pub struct FakeSmb {
+    pub configs: Vec<(PathBuf, String)>,
+    pub reloads: usize,
+    pub passwords: Vec<(String, String)>,
+    pub fail: bool,
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl FakeSmb {
+    fn check(&self) -> io::Result<()> {
+        if self.fail {
+            return Err(io::Error::other("fake smb failure"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl SmbBackend for FakeSmb {
+    fn write_config(&mut self, path: &Path, contents: &str) -> io::Result<()> {
+        self.check()?;
+        self.configs
+            .push((path.to_path_buf(), contents.to_string()));
+        Ok(())
+    }
+
+    fn reload(&mut self) -> io::Result<()> {
+        self.check()?;
+        self.reloads += 1;
+        Ok(())
+    }
+
+    fn set_password(&mut self, username: &str, password: &str) -> io::Result<()> {
+        self.check()?;
+        self.passwords
+            .push((username.to_string(), password.to_string()));
+        Ok(())
+    }
+}
+
+/// Lets a caller keep a handle on a fake backend after handing it to the app
+#[cfg(any(test, feature = "fake"))]
+impl<T: SmbBackend> SmbBackend for std::rc::Rc<std::cell::RefCell<T>> {
+    fn write_config(&mut self, path: &Path, contents: &str) -> io::Result<()> {
+        self.borrow_mut().write_config(path, contents)
+    }
+
+    fn reload(&mut self) -> io::Result<()> {
+        self.borrow_mut().reload()
+    }
+
+    fn set_password(&mut self, username: &str, password: &str) -> io::Result<()> {
+        self.borrow_mut().set_password(username, password)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, mounted: bool) -> Device {
+        Device {
+            name: name.to_string(),
+            mounted,
+            path: PathBuf::from("/mnt").join(format!("{name}-USB")),
+            ..Default::default()
+        }
+    }
+
+    fn config() -> SmbConfig {
+        SmbConfig {
+            username: "pi".to_string(),
+            conf_path: PathBuf::from("/etc/samba/drive-pi.conf"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shares_are_named_after_the_mount_point() {
+        let shares = shares(&[device("sda1", true), device("sdb1", false)]);
+
+        assert_eq!(
+            shares,
+            vec![
+                Share {
+                    name: "sda1-USB".to_string(),
+                    path: PathBuf::from("/mnt/sda1-USB"),
+                    shared: true,
+                    read_only: false,
+                },
+                Share {
+                    name: "sdb1-USB".to_string(),
+                    path: PathBuf::from("/mnt/sdb1-USB"),
+                    shared: false,
+                    read_only: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn config_only_exports_mounted_devices() {
+        let contents = generate_config(
+            &shares(&[device("sda1", true), device("sdb1", false)]),
+            &config(),
+        );
+
+        assert_eq!(
+            contents,
+            "# Generated by drive-pi, changes will be overwritten\n\
+             \n\
+             [sda1-USB]\n   \
+             path = /mnt/sda1-USB\n   \
+             valid users = pi\n   \
+             read only = no\n   \
+             browseable = yes\n"
+        );
+    }
+
+    #[test]
+    fn read_only_mounts_are_read_only_shares() {
+        let mut dev = device("sda1", true);
+        dev.options = vec!["ro".to_string(), "noatime".to_string()];
+
+        let contents = generate_config(&shares(&[dev]), &config());
+
+        assert!(contents.contains("   read only = yes\n"));
+    }
+
+    #[test]
+    fn update_writes_the_config_then_reloads() {
+        let mut smb = FakeSmb::default();
+
+        let shared = update(&mut smb, &[device("sda1", true)], &config()).unwrap();
+
+        assert_eq!(shared.len(), 1);
+        assert_eq!(smb.configs.len(), 1);
+        assert_eq!(smb.configs[0].0, PathBuf::from("/etc/samba/drive-pi.conf"));
+        assert!(smb.configs[0].1.contains("[sda1-USB]"));
+        assert_eq!(smb.reloads, 1);
+    }
+
+    #[test]
+    fn failed_write_skips_the_reload() {
+        let mut smb = FakeSmb {
+            fail: true,
+            ..Default::default()
+        };
+
+        assert!(update(&mut smb, &[device("sda1", true)], &config()).is_err());
+        assert_eq!(smb.reloads, 0);
+    }
+}
@@ -0,0 +1,111 @@
+use std::io::ErrorKind;
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    device::{Device, MountOptions},
+    smb::Share,
+};
+
+/// A mount drive-pi made, kept until the drive is ejected so it can be
+/// picked up again after a restart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OwnedMount {
+    /// Kernel name at the time, only used to find drives without a UUID
+    pub name: String,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    pub path: PathBuf,
+    pub options: MountOptions,
+}
+
+impl OwnedMount {
+    pub fn new(device: &Device, options: &MountOptions) -> Self {
+        Self {
+            name: device.name.clone(),
+            uuid: device.uuid.clone(),
+            path: device.path.clone(),
+            options: options.clone(),
+        }
+    }
+
+    /// Whether `device` is the drive this was mounted from, kernel names
+    /// change between boots so the UUID wins when there is one
+    pub fn matches(&self, device: &Device) -> bool {
+        match (&self.uuid, &device.uuid) {
+            (Some(ours), Some(theirs)) => ours == theirs,
+            (None, _) => self.name == device.name,
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// What drive-pi has changed on the system, kept on disk so it can be
+/// tidied up or picked up again after a restart or a crash
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct State {
+    /// Mount points drive-pi created, the only ones it may remove
+    #[serde(default)]
+    pub created_dirs: BTreeSet<PathBuf>,
+    /// Mounts drive-pi made, anything else under the mount root belongs to
+    /// the system
+    #[serde(default)]
+    pub mounts: Vec<OwnedMount>,
+    /// Shares last exported to Samba
+    #[serde(default)]
+    pub shares: Vec<Share>,
+}
+
+impl State {
+    /// Paths of the mounts drive-pi owns
+    pub fn owned_paths(&self) -> Vec<PathBuf> {
+        self.mounts.iter().map(|x| x.path.clone()).collect()
+    }
+
+    /// Records `mount`, replacing whatever was recorded at the same path
+    pub fn add_mount(&mut self, mount: OwnedMount) {
+        self.remove_mount(&mount.path);
+        self.mounts.push(mount);
+    }
+
+    pub fn remove_mount(&mut self, path: &Path) {
+        self.mounts.retain(|x| x.path != path);
+    }
+
+    /// Keeps the recorded options in step with a remount
+    pub fn set_read_only(&mut self, path: &Path, read_only: bool) {
+        if let Some(mount) = self.mounts.iter_mut().find(|x| x.path == path) {
+            let options = &mut mount.options.options;
+            options.retain(|x| x != "ro" && x != "rw");
+            options.insert(0, if read_only { "ro" } else { "rw" }.to_string());
+        }
+    }
+
+    /// Reads the state file, a missing file is an empty state
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let source = match fs::read_to_string(path) {
+            Ok(val) => val,
+            Err(ex) if ex.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(ex) => return Err(ex),
+        };
+
+        serde_json::from_str(&source).map_err(|ex| io::Error::new(ErrorKind::InvalidData, ex))
+    }
+
+    /// Writes the state next to `path` first, so a crash part way through
+    /// never leaves half a file
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(temp, path)
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use crate::{
+    app::App,
+    framebuffer::FrameBuffer,
+    hotplug::{FakeHotplug, HotplugSource, SysBlock},
+    input::Event,
+    logging::LogBuffer,
+};
+
+pub struct Options {
+    /// Run against the in memory backends instead of the real tools
+    pub fake: bool,
+    /// Draw with braille, which fits in a quarter of the space
+    pub braille: bool,
+}
+
+/// Puts the terminal back how it was, even if drawing fails
+struct Restore;
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn render(display: &FrameBuffer, braille: bool, help: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+
+    let lines = if braille {
+        display.to_braille()
+    } else {
+        display.to_half_blocks()
+    };
+
+    for (row, line) in lines.iter().enumerate() {
+        queue!(stdout, MoveTo(0, row as u16), Print(line))?;
+    }
+    queue!(stdout, MoveTo(0, lines.len() as u16 + 1), Print(help))?;
+
+    stdout.flush()
+}
+
+pub fn run(options: Options) -> io::Result<()> {
+    // Stderr is the terminal being drawn on, so only log somewhere else
+    let logs = LogBuffer::default();
+    let (config, _log) = crate::startup(&logs, true);
+
+    let (mut app, fake) = if options.fake {
+        let (app, block) = App::demo(config, logs);
+        (app, Some(block))
+    } else {
+        let mut app = App::new(config, logs);
+        crate::resume(&mut app);
+        (app, None)
+    };
+
+    // The fake backend is plugged from the keyboard
+    let mut hotplug: Option<Box<dyn HotplugSource>> = match &fake {
+        Some(block) => Some(Box::new(FakeHotplug::new(block.clone()))),
+        None => SysBlock::new("/sys/block")
+            .ok()
+            .map(|x| Box::new(x) as Box<dyn HotplugSource>),
+    };
+    let mut plugged = 0u8;
+
+    let mut display = FrameBuffer::for_config(&app.config().hardware);
+    let help = if fake.is_some() {
+        "q quit, + plug, - unplug"
+    } else {
+        "q quit"
+    };
+
+    terminal::enable_raw_mode()?;
+    let _restore = Restore;
+    execute!(
+        io::stdout(),
+        EnterAlternateScreen,
+        Hide,
+        Clear(ClearType::All)
+    )?;
+
+    let mut redraw = true;
+
+    while !app.should_exit() {
+        if redraw {
+            display.clear(BinaryColor::Off).unwrap();
+            app.draw(&mut display).unwrap();
+            render(&display, options.braille, help)?;
+        }
+
+        let mut events = vec![Event::Tick(Instant::now())];
+
+        if event::poll(Duration::from_millis(250))? {
+            match event::read()? {
+                TermEvent::Key(KeyEvent {
+                    code,
+                    modifiers,
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => match code {
+                    KeyCode::Esc => events.push(Event::Shutdown),
+                    KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        events.push(Event::Shutdown)
+                    }
+                    KeyCode::Char(c) => {
+                        let key = c.to_string();
+
+                        match (&fake, c) {
+                            (_, 'q') => events.push(Event::Shutdown),
+                            (Some(block), '+') if plugged < 25 => {
+                                plugged += 1;
+                                let name = format!("sd{}1", (b'a' + plugged) as char);
+                                block.borrow_mut().plug(&name, "32G");
+                            }
+                            (Some(block), '-') if plugged > 0 => {
+                                let name = format!("sd{}1", (b'a' + plugged) as char);
+                                plugged -= 1;
+                                block.borrow_mut().unplug(&name);
+                            }
+                            _ => {
+                                if let Some(button) = app.config().input.button_for_key(&key) {
+                                    events.push(Event::Press(button));
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                TermEvent::Resize(..) => {
+                    execute!(io::stdout(), Clear(ClearType::All))?;
+                    redraw = true;
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        // Every pass rather than once a second, so fake plugs show at once
+        if let Some(Ok(val)) = hotplug.as_mut().map(|x| x.poll()) {
+            events.extend(val.into_iter().map(Event::Hotplug));
+        }
+
+        redraw = false;
+        for event in events {
+            redraw |= app.handle_event(event);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use std::io;
+use std::process::Command;
+
+use crate::config::WifiConfig;
+
+/// Name of the NetworkManager connection used for the hotspot
+const HOTSPOT_CONNECTION: &str = "drive-pi-hotspot";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WifiState {
+    Disconnected,
+    /// Only NetworkManager reports this, the fake never joins a network
+    Connected(String),
+    Hotspot(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    pub ssid: String,
+    pub signal: u8,
+    pub secured: bool,
+}
+
+/// Controls the wireless interface, implemented by [`Nmcli`] on the Pi
+pub trait WifiBackend {
+    fn state(&mut self, config: &WifiConfig) -> io::Result<WifiState>;
+    fn scan(&mut self, config: &WifiConfig) -> io::Result<Vec<Network>>;
+    fn hotspot_up(&mut self, config: &WifiConfig) -> io::Result<()>;
+    fn hotspot_down(&mut self, config: &WifiConfig) -> io::Result<()>;
+}
+
+/// WiFi backend that drives NetworkManager through `nmcli`
+pub struct Nmcli;
+
+impl Nmcli {
+    fn run(args: &[&str]) -> io::Result<String> {
+        let output = Command::new("nmcli").args(args).output()?;
+
+        if !output.status.success() {
+            return Err(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl WifiBackend for Nmcli {
+    fn state(&mut self, config: &WifiConfig) -> io::Result<WifiState> {
+        let output = Self::run(&["-t", "-f", "NAME,DEVICE", "con", "show", "--active"])?;
+
+        for line in output.lines() {
+            let fields = split_terse(line);
+            if fields.len() < 2 || fields[1] != config.interface {
+                continue;
+            }
+
+            if fields[0] == HOTSPOT_CONNECTION {
+                return Ok(WifiState::Hotspot(config.ssid.clone()));
+            }
+
+            return Ok(WifiState::Connected(fields[0].clone()));
+        }
+
+        Ok(WifiState::Disconnected)
+    }
+
+    fn scan(&mut self, config: &WifiConfig) -> io::Result<Vec<Network>> {
+        let output = Self::run(&[
+            "-t",
+            "-f",
+            "SSID,SIGNAL,SECURITY",
+            "dev",
+            "wifi",
+            "list",
+            "ifname",
+            &config.interface,
+        ])?;
+
+        Ok(parse_scan(&output))
+    }
+
+    fn hotspot_up(&mut self, config: &WifiConfig) -> io::Result<()> {
+        Self::run(&[
+            "dev",
+            "wifi",
+            "hotspot",
+            "ifname",
+            &config.interface,
+            "con-name",
+            HOTSPOT_CONNECTION,
+            "ssid",
+            &config.ssid,
+            "password",
+            &config.pass,
+        ])?;
+
+        Ok(())
+    }
+
+    fn hotspot_down(&mut self, _config: &WifiConfig) -> io::Result<()> {
+        Self::run(&["con", "down", HOTSPOT_CONNECTION])?;
+
+        Ok(())
+    }
+}
+
+/// Parses `nmcli -t -f SSID,SIGNAL,SECURITY dev wifi list`, strongest first
+///
+/// Access points sharing an SSID are listed once, with the best signal, and
+/// hidden networks without an SSID are left out.
+fn parse_scan(output: &str) -> Vec<Network> {
+    let mut networks: Vec<Network> = vec![];
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+        if fields.len() < 3 || fields[0].is_empty() {
+            continue;
+        }
+
+        let network = Network {
+            ssid: fields[0].clone(),
+            signal: fields[1].parse().unwrap_or(0),
+            secured: !fields[2].is_empty() && fields[2] != "--",
+        };
+
+        match networks.iter_mut().find(|x| x.ssid == network.ssid) {
+            Some(seen) if seen.signal < network.signal => *seen = network,
+            Some(_) => {}
+            None => networks.push(network),
+        }
+    }
+
+    networks.sort_by_key(|x| std::cmp::Reverse(x.signal));
+    networks
+}
+
+/// Splits a line of `nmcli -t` output, which separates fields with `:` and
+/// escapes literal colons as `\:`
+fn split_terse(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    fields.last_mut().unwrap().push(escaped);
+                }
+            }
+            ':' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+
+    fields
+}
+
+/// In memory WiFi backend for exercising the UI without NetworkManager
+#[cfg(any(test, feature = "fake"))]
+#[derive(Default)]
+pub struct FakeWifi {
+    pub state: Option<WifiState>,
+    pub networks: Vec<Network>,
+    pub fail: bool,
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl FakeWifi {
+    fn check(&self) -> io::Result<()> {
+        if self.fail {
+            return Err(io::Error::other("fake wifi failure"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl WifiBackend for FakeWifi {
+    fn state(&mut self, _config: &WifiConfig) -> io::Result<WifiState> {
+        self.check()?;
+        Ok(self.state.clone().unwrap_or(WifiState::Disconnected))
+    }
+
+    fn scan(&mut self, _config: &WifiConfig) -> io::Result<Vec<Network>> {
+        self.check()?;
+        Ok(self.networks.clone())
+    }
+
+    fn hotspot_up(&mut self, config: &WifiConfig) -> io::Result<()> {
+        self.check()?;
+        self.state = Some(WifiState::Hotspot(config.ssid.clone()));
+        Ok(())
+    }
+
+    fn hotspot_down(&mut self, _config: &WifiConfig) -> io::Result<()> {
+        self.check()?;
+        self.state = Some(WifiState::Disconnected);
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "fake"))]
+impl<T: WifiBackend> WifiBackend for std::rc::Rc<std::cell::RefCell<T>> {
+    fn state(&mut self, config: &WifiConfig) -> io::Result<WifiState> {
+        self.borrow_mut().state(config)
+    }
+
+    fn scan(&mut self, config: &WifiConfig) -> io::Result<Vec<Network>> {
+        self.borrow_mut().scan(config)
+    }
+
+    fn hotspot_up(&mut self, config: &WifiConfig) -> io::Result<()> {
+        self.borrow_mut().hotspot_up(config)
+    }
+
+    fn hotspot_down(&mut self, config: &WifiConfig) -> io::Result<()> {
+        self.borrow_mut().hotspot_down(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(ssid: &str, signal: u8, secured: bool) -> Network {
+        Network {
+            ssid: ssid.to_string(),
+            signal,
+            secured,
+        }
+    }
+
+    #[test]
+    fn split_terse_fields() {
+        assert_eq!(split_terse("Home:wlan0"), vec!["Home", "wlan0"]);
+        assert_eq!(split_terse("Home::"), vec!["Home", "", ""]);
+        assert_eq!(split_terse(""), vec![""]);
+    }
+
+    #[test]
+    fn split_terse_escapes() {
+        assert_eq!(split_terse(r"Cafe\:Free:72:"), vec!["Cafe:Free", "72", ""]);
+        assert_eq!(split_terse(r"back\\slash:1"), vec![r"back\slash", "1"]);
+    }
+
+    #[test]
+    fn scan_parsing() {
+        let output = "Home:82:WPA2\nCafe\\:Free:40:\nOld:20:--\n:90:WPA2\nbroken\n";
+
+        assert_eq!(
+            parse_scan(output),
+            vec![
+                network("Home", 82, true),
+                network("Cafe:Free", 40, false),
+                network("Old", 20, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_keeps_the_best_signal() {
+        // Not in signal order, as with `--rescan no` or a different sort
+        let output = "Home:30:WPA2\nCafe:50:\nHome:75:WPA2\nHome:60:WPA2\n";
+
+        assert_eq!(
+            parse_scan(output),
+            vec![network("Home", 75, true), network("Cafe", 50, false)]
+        );
+    }
+}